@@ -1,51 +1,73 @@
-use chrono::prelude::Local;
-use chrono::{DateTime, Duration, DurationRound, Timelike, Utc};
-use chrono_tz::America::New_York;
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, TimeZone, Utc};
 use chrono_tz::Tz;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Read, Write};
 use std::path::Path;
 use std::path::PathBuf;
-use tracing::{error, info};
+use std::str::FromStr;
+use tracing::{error, info, warn};
 
-#[derive(Debug, Deserialize)]
-struct IQFeedTick {
-    #[serde(with = "crate::iqfeed_date_time")]
-    date_time: DateTime<Tz>,
-    request_id: u32,
-    last: f64,
-    last_size: f64,
-    total_volume: f64,
-    bid: f64,
-    ask: f64,
-    tick_id: u64,
-    basis_for_last: String,
-    trade_market_center: u32,
-    trade_conditions: String,
-    trade_aggressor: String,
-}
-
-#[derive(Copy, Clone)]
-pub enum Timestamp {
-    IQFeed,
-    Unix,
-}
+use crate::classify::{Classification, Classifier};
+use crate::profile::{ColumnIndexes, Profile};
+use crate::session::{BoundaryCache, Session};
+use crate::stats::{Ewma, Window};
+use crate::timestamp::DstPolicy;
 
 pub struct BarOptions<'o> {
-    pub delimiter: String,
+    // how to read the tick files
+    pub profile: Profile,
     pub multiply: f64,
     pub symbol: &'o String,
-    pub timestamp_index: usize,
-    pub last_index: usize,
-    pub volume_index: usize,
-    pub timestamp_type: Timestamp,
+    pub classification: Classification,
+    pub bvc_span: f64,
+    pub columns: Vec<Column>,
+    pub incremental: bool,
+    pub output: Option<String>,
+    pub retention: Retention,
     pub dollar_threshold: f64,
+    pub tick_threshold: u64,
+    pub volume_threshold: f64,
+    pub expected_imbalance_span: f64,
+    // estimate E[b] of imbalance bars per bar rather than per tick
+    pub expected_imbalance_per_bar: bool,
+    pub expected_ticks_span: f64,
+    pub warmup_bars: u64,
+    pub min_expected_ticks: f64,
+    pub max_expected_ticks: Option<f64>,
+    pub interval: String,
+    pub timezone: Tz,
+    pub session: Option<Session>,
+    pub drop_out_of_session: bool,
+    pub bars_per_day: Option<f64>,
+    pub threshold_lookback_days: usize,
+    pub tick_size: f64,
+    pub range_ticks: u64,
+    pub brick_size: f64,
 }
 
-fn list_tick_files(in_dir_path: PathBuf) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+// Every type of bar that can be sampled, by the name it's given on the
+// command line.
+pub const BAR_TYPES: [&str; 12] = [
+    "time",
+    "dollar",
+    "tick",
+    "volume",
+    "tick_imbalance",
+    "volume_imbalance",
+    "dollar_imbalance",
+    "tick_run",
+    "volume_run",
+    "dollar_run",
+    "range",
+    "renko",
+];
+
+pub fn list_tick_files(in_dir_path: PathBuf) -> Result<Vec<PathBuf>, Box<dyn Error>> {
     let mut tick_files = fs::read_dir(in_dir_path)?
         .filter_map(|d| {
             d.ok().and_then(|f| {
@@ -65,173 +87,2365 @@ fn list_tick_files(in_dir_path: PathBuf) -> Result<Vec<PathBuf>, Box<dyn Error>>
     Ok(tick_files)
 }
 
-pub fn time_bars(symbol: &str, interval: &str) -> Result<(), Box<dyn Error>> {
-    let (mut open, mut high, mut low, mut cumulative_dollar, mut cumulative_volume) =
-        (0.0, 0.0, 0.0, 0.0, 0.0);
+// Parses intervals such as 30s, 5m, 1h or 1d. A bare number is taken to be
+// in minutes.
+pub fn parse_interval(interval: &str) -> Result<Duration, Box<dyn Error>> {
+    let (n, unit) = match interval.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => interval.split_at(i),
+        None => (interval, "m"),
+    };
+    let n = n.parse::<i64>()?;
+    let parsed = match unit {
+        "s" => Duration::seconds(n),
+        "m" => Duration::minutes(n),
+        "h" => Duration::hours(n),
+        "d" => Duration::days(n),
+        _ => return Err(format!("Unknown interval unit {:?}", unit).into()),
+    };
+    if parsed <= Duration::zero() {
+        return Err(format!("Interval must be positive, got {:?}", interval).into());
+    }
+    Ok(parsed)
+}
+
+// Start of the interval that date_time falls in. Intervals are counted from
+// local midnight on 1970-01-01 in date_time's timezone, so those that divide a
+// day, e.g. 1h or 1d, start at local midnight even across DST changes, while
+// others, e.g. 7m or 5h, start at a different time each day. In the hour
+// repeated when clocks go back this can be earlier than the start of the
+// interval before, see TimeSampler::starts_bar.
+fn interval_start(date_time: DateTime<Tz>, interval: Duration) -> DateTime<Tz> {
+    let local = date_time.naive_local();
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let offset =
+        Duration::nanoseconds(nanoseconds(local - epoch).rem_euclid(nanoseconds(interval)));
+    match date_time.timezone().from_local_datetime(&(local - offset)) {
+        LocalResult::Single(start) => start,
+        // e.g. the repeated hour when clocks go back, use whichever occurrence
+        // this tick actually belongs to
+        LocalResult::Ambiguous(earliest, latest) => {
+            if latest <= date_time {
+                latest
+            } else {
+                earliest
+            }
+        }
+        // the local start of the interval was skipped by a DST change
+        LocalResult::None => date_time - offset,
+    }
+}
+
+// With a session, intervals are aligned to the last session open or close
+// instead, so that e.g. 1h bars for a 09:30 open start on the half hour and 1d
+// bars cover the whole session.
+fn session_interval_start(
+    date_time: DateTime<Tz>,
+    boundary: DateTime<Tz>,
+    interval: Duration,
+) -> DateTime<Tz> {
+    let elapsed = nanoseconds(date_time - boundary);
+    boundary + Duration::nanoseconds(elapsed - elapsed % nanoseconds(interval))
+}
 
-    // TODO: These defaults are bugs waiting to happen. (e.g., what if price == 0)
-    // Need to figure out a better approach for this, and write some tests.
-    let mut bartime: DateTime<Tz> = chrono::MIN_DATETIME.with_timezone(&New_York);
-    let parsed_interval = interval.parse::<u32>().unwrap();
-    let mut last_printed_minute = 0;
+// Ticks can be nanoseconds apart, which fits in an i64 until 2262.
+fn nanoseconds(duration: Duration) -> i64 {
+    duration.num_nanoseconds().unwrap()
+}
 
-    let out_dir_path = Path::new("bars").join(symbol);
-    let in_dir_path = Path::new("ticks").join(symbol);
+// Time bars are sampled whenever a tick falls in a new interval, and are
+// stamped with the time their interval started rather than the time of their
+// first tick.
+#[derive(Serialize, Deserialize)]
+struct TimeSampler {
+    interval_ms: i64,
+    timezone: Tz,
+    start: Option<DateTime<Utc>>,
+}
 
-    fs::create_dir_all(out_dir_path.to_str().unwrap())?;
-    let now_dt = Utc::now().with_timezone(&New_York);
-    let file_name = format!("{}.csv", now_dt.format("time-%Y-%m-%d-%H-%M-%S"));
-    let out_path = out_dir_path.join(file_name);
-    let mut out_file = File::create(&out_path)?;
-    info!(
-        out_file = out_path.to_str().unwrap(),
-        interval = interval,
-        "Sampling time bars"
+impl Sampler for TimeSampler {
+    fn needs_date_time(&self) -> bool {
+        true
+    }
+
+    fn starts_bar(&mut self, tick: &Tick) -> bool {
+        let date_time = tick.date_time.unwrap().with_timezone(&self.timezone);
+        let interval = Duration::milliseconds(self.interval_ms);
+        let start = match tick.boundary {
+            Some(boundary) => {
+                session_interval_start(date_time, boundary.with_timezone(&self.timezone), interval)
+            }
+            None => interval_start(date_time, interval),
+        }
+        .with_timezone(&Utc);
+        // After clocks go back the local start of the interval can be before
+        // that of the current bar, e.g. 00:00 again at 01:00 EST for 90m bars,
+        // so the current bar carries on until the next interval starts.
+        if self.start.is_some_and(|current| start < current) {
+            return false;
+        }
+        let starts_bar = self.start != Some(start);
+        self.start = Some(start);
+        starts_bar
+    }
+
+    fn open_time(&self, _tick: &Tick) -> Option<String> {
+        self.start.map(|start| {
+            format!(
+                "{}",
+                start
+                    .with_timezone(&self.timezone)
+                    .format(crate::iqfeed_date_time::FORMAT)
+            )
+        })
+    }
+
+    fn should_sample(&self, _bar: &Bar) -> bool {
+        false
+    }
+
+    fn save(&self) -> Result<toml::Value, Box<dyn Error>> {
+        Ok(toml::Value::try_from(self)?)
+    }
+
+    fn restore(&mut self, state: toml::Value) -> Result<(), Box<dyn Error>> {
+        *self = state.try_into()?;
+        Ok(())
+    }
+}
+
+pub fn time_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    let interval = parse_interval(&opts.interval)?;
+    sample_bars(
+        opts,
+        "time",
+        TimeSampler {
+            interval_ms: interval.num_milliseconds(),
+            timezone: opts.timezone,
+            start: None,
+        },
+    )
+}
+
+const HEADER: &str = "date_time,open,high,low,close,volume,cum_dollars,\
+                      buy_volume,sell_volume,buy_dollars,sell_dollars";
+
+// Optional columns that can be added to every type of bar with --columns.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Column {
+    Vwap,
+    NTicks,
+    AvgSpread,
+    // spreads weighted by how long they were quoted for
+    TimeWeightedSpread,
+    // the last bid and ask in the bar
+    Bid,
+    Ask,
+}
+
+impl FromStr for Column {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Column, Box<dyn Error>> {
+        match s {
+            "vwap" => Ok(Column::Vwap),
+            "n_ticks" => Ok(Column::NTicks),
+            "avg_spread" => Ok(Column::AvgSpread),
+            "time_weighted_spread" => Ok(Column::TimeWeightedSpread),
+            "bid" => Ok(Column::Bid),
+            "ask" => Ok(Column::Ask),
+            _ => Err(format!("Unknown column {:?}", s).into()),
+        }
+    }
+}
+
+impl Column {
+    fn name(self) -> &'static str {
+        match self {
+            Column::Vwap => "vwap",
+            Column::NTicks => "n_ticks",
+            Column::AvgSpread => "avg_spread",
+            Column::TimeWeightedSpread => "time_weighted_spread",
+            Column::Bid => "bid",
+            Column::Ask => "ask",
+        }
+    }
+}
+
+fn header(columns: &[Column]) -> String {
+    let mut header = String::from(HEADER);
+    for column in columns {
+        header.push(',');
+        header.push_str(column.name());
+    }
+    header
+}
+
+// The fields of a tick that bars are sampled from.
+struct Tick {
+    // only parsed when needed, see Sampler::needs_date_time
+    date_time: Option<DateTime<Tz>>,
+    last: f64,
+    volume: f64,
+    // the session open or close before the tick, if there's a session
+    boundary: Option<DateTime<Tz>>,
+    // bid and ask, see parse_quote
+    quote: Option<(f64, f64)>,
+    // 1 for a buy, -1 for a sell and 0 if the trade couldn't be classified
+    side: f64,
+}
+
+fn parse_field<T: FromStr>(record: &csv::ByteRecord, index: Option<usize>) -> Option<T> {
+    record
+        .get(index?)
+        .and_then(|field| std::str::from_utf8(field).ok())
+        .and_then(|field| field.parse::<T>().ok())
+}
+
+// The aggressor of a trade, 1 for buys and 2 for sells as IQFeed reports
+// them, or as words.
+fn parse_aggressor(record: &csv::ByteRecord, index: Option<usize>) -> Option<u32> {
+    let aggressor = record.get(index?)?;
+    match aggressor {
+        b"buy" | b"Buy" | b"BUY" => Some(1),
+        b"sell" | b"Sell" | b"SELL" => Some(2),
+        _ => std::str::from_utf8(aggressor).ok()?.parse::<u32>().ok(),
+    }
+}
+
+// Open time for a bar starting with tick, in the same format and timezone as
+// time bars use so that all bars look alike, whatever the tick files are in.
+fn tick_open_time(
+    opts: &BarOptions,
+    timestamp: &[u8],
+    tick: &Tick,
+) -> Result<String, Box<dyn Error>> {
+    let date_time = match tick.date_time {
+        Some(date_time) => date_time,
+        None => opts
+            .profile
+            .parse_timestamp(&String::from_utf8_lossy(timestamp))?
+            .ok_or("Dropped tick opened a bar")?,
+    };
+    Ok(format!(
+        "{}",
+        date_time
+            .with_timezone(&opts.timezone)
+            .format(crate::iqfeed_date_time::FORMAT)
+    ))
+}
+
+// The bid and ask of a tick, if it has a valid quote. IQFeed reports a bid of
+// 0 when there isn't one.
+fn parse_quote(record: &csv::ByteRecord, columns: &ColumnIndexes) -> Option<(f64, f64)> {
+    let bid = parse_field::<f64>(record, columns.bid)?;
+    let ask = parse_field::<f64>(record, columns.ask)?;
+    if bid > 0. && ask >= bid {
+        Some((bid, ask))
+    } else {
+        None
+    }
+}
+
+// OHLCV state of the bar currently being sampled
+#[derive(Serialize, Deserialize)]
+struct Bar {
+    open_time: String,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: f64,
+    dollars: f64,
+    buy_volume: f64,
+    sell_volume: f64,
+    buy_dollars: f64,
+    sell_dollars: f64,
+    n_ticks: u64,
+    // sum of price times volume, for vwap
+    price_volume: f64,
+    spreads: f64,
+    n_quotes: u64,
+    // sum of each spread times the seconds until the next quote
+    spread_seconds: f64,
+    quote_seconds: f64,
+    last_quote: Option<(f64, f64)>,
+    last_quote_time: Option<DateTime<Utc>>,
+}
+
+impl Bar {
+    fn new(open_time: String, last: f64) -> Bar {
+        Bar {
+            open_time,
+            open: last,
+            high: last,
+            low: last,
+            close: last,
+            volume: 0.0,
+            dollars: 0.0,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            buy_dollars: 0.0,
+            sell_dollars: 0.0,
+            n_ticks: 0,
+            price_volume: 0.0,
+            spreads: 0.0,
+            n_quotes: 0,
+            spread_seconds: 0.0,
+            quote_seconds: 0.0,
+            last_quote: None,
+            last_quote_time: None,
+        }
+    }
+
+    fn update(&mut self, tick: &Tick, multiply: f64) {
+        let (last, volume) = (tick.last, tick.volume);
+        if last < self.low {
+            self.low = last;
+        }
+        if last > self.high {
+            self.high = last;
+        }
+        self.close = last;
+        let dollars = last * volume * multiply;
+        self.volume += volume;
+        self.dollars += dollars;
+        if tick.side > 0. {
+            self.buy_volume += volume;
+            self.buy_dollars += dollars;
+        } else if tick.side < 0. {
+            self.sell_volume += volume;
+            self.sell_dollars += dollars;
+        }
+        self.n_ticks += 1;
+        self.price_volume += last * volume;
+        if let Some((bid, ask)) = tick.quote {
+            self.spreads += ask - bid;
+            self.n_quotes += 1;
+            // date_time is only parsed when the time weighted spread is needed
+            if let (Some(date_time), Some(last_quote_time), Some((last_bid, last_ask))) =
+                (tick.date_time, self.last_quote_time, self.last_quote)
+            {
+                let seconds = (date_time.with_timezone(&Utc) - last_quote_time)
+                    .num_nanoseconds()
+                    .unwrap_or(0) as f64
+                    / 1e9;
+                self.spread_seconds += (last_ask - last_bid) * seconds;
+                self.quote_seconds += seconds;
+            }
+            self.last_quote = Some((bid, ask));
+            self.last_quote_time = tick
+                .date_time
+                .map(|date_time| date_time.with_timezone(&Utc));
+        }
+    }
+
+    fn column(&self, column: Column) -> f64 {
+        let last_spread = self.last_quote.map(|(bid, ask)| ask - bid);
+        match column {
+            Column::Vwap => self.price_volume / self.volume,
+            Column::NTicks => self.n_ticks as f64,
+            Column::AvgSpread => self.spreads / self.n_quotes as f64,
+            // if every quote in the bar was at the same time, the last one is
+            // all there is to go on
+            Column::TimeWeightedSpread if self.quote_seconds > 0. => {
+                self.spread_seconds / self.quote_seconds
+            }
+            Column::TimeWeightedSpread => last_spread.unwrap_or(f64::NAN),
+            Column::Bid => self.last_quote.map_or(f64::NAN, |(bid, _)| bid),
+            Column::Ask => self.last_quote.map_or(f64::NAN, |(_, ask)| ask),
+        }
+    }
+
+    // buy_fraction splits the volume of the whole bar into buys and sells
+    // instead of using the classification of each trade, see
+    // Classifier::buy_fraction. extra_columns are written before the
+    // sampler's columns.
+    fn write(
+        &self,
+        out: &mut impl Write,
+        buy_fraction: Option<f64>,
+        extra_columns: &[Column],
+        columns: &str,
+    ) -> std::io::Result<()> {
+        let (buy_volume, sell_volume, buy_dollars, sell_dollars) = match buy_fraction {
+            Some(fraction) => (
+                self.volume * fraction,
+                self.volume * (1. - fraction),
+                self.dollars * fraction,
+                self.dollars * (1. - fraction),
+            ),
+            None => (
+                self.buy_volume,
+                self.sell_volume,
+                self.buy_dollars,
+                self.sell_dollars,
+            ),
+        };
+        let mut extra = String::new();
+        for column in extra_columns {
+            extra.push_str(&format!(",{}", self.column(*column)));
+        }
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{},{}{}{}",
+            self.open_time,
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume,
+            self.dollars,
+            buy_volume,
+            sell_volume,
+            buy_dollars,
+            sell_dollars,
+            extra,
+            columns
+        )
+    }
+}
+
+// Decides when the bar in progress should be sampled. Samplers that need to
+// track state across ticks or bars (e.g. imbalance bars) implement this
+// directly, while simple threshold samplers can just be a closure.
+trait Sampler {
+    // Extra columns appended to the standard bar header, each with a leading
+    // comma.
+    fn header(&self) -> &'static str {
+        ""
+    }
+
+    // Whether update needs the tick's date_time, which is otherwise only
+    // parsed when a session is set.
+    fn needs_date_time(&self) -> bool {
+        false
+    }
+
+    // Whether update needs every tick to be classified as a buy or sell,
+    // which bulk volume classification doesn't do.
+    fn needs_trade_sides(&self) -> bool {
+        false
+    }
+
+    // Called for every tick before it's added to the bar in progress. If it
+    // returns true, the bar in progress is written out and tick starts a new
+    // one instead.
+    fn starts_bar(&mut self, _tick: &Tick) -> bool {
+        false
+    }
+
+    // Open time for a new bar starting with tick, if it shouldn't just be the
+    // timestamp of tick.
+    fn open_time(&self, _tick: &Tick) -> Option<String> {
+        None
+    }
+
+    // Called for every tick after it has been added to bar.
+    fn update(&mut self, _bar: &Bar, _tick: &Tick) {}
+
+    fn should_sample(&self, bar: &Bar) -> bool;
+
+    // Values for the extra header columns for bar.
+    fn columns(&self, _bar: &Bar) -> String {
+        String::new()
+    }
+
+    // Called once bar has been written out.
+    fn reset(&mut self, _bar: &Bar) {}
+
+    // State to carry over to the next run when sampling incrementally.
+    fn save(&self) -> Result<toml::Value, Box<dyn Error>> {
+        Ok(toml::Value::Table(toml::value::Table::new()))
+    }
+
+    fn restore(&mut self, _state: toml::Value) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl<F> Sampler for F
+where
+    F: Fn(&Bar) -> bool,
+{
+    fn should_sample(&self, bar: &Bar) -> bool {
+        self(bar)
+    }
+}
+
+// Where an incremental run left off, saved next to its bars so that the next
+// run only has to process ticks that have been pulled since.
+#[derive(Serialize, Deserialize)]
+struct SamplerState {
+    // bars sampled with different options are started over
+    parameters: String,
+    // the last tick file processed, and how far into it
+    tick_file: String,
+    offset: u64,
+    // the length of the bars file when the state was saved, so that bars
+    // written after it by a run that failed can be dropped
+    bars_length: Option<u64>,
+    bar_boundary: Option<(DateTime<Utc>, bool)>,
+    prev_tick_timestamp: String,
+    // missing from state saved before bars waited for the end of a run of
+    // ticks at the same time
+    #[serde(default)]
+    sample_pending: bool,
+    bar: Option<Bar>,
+    classifier: Classifier,
+    sampler: toml::Value,
+}
+
+impl SamplerState {
+    // Loads the state at path, unless there isn't any or it's for bars with
+    // different parameters.
+    fn load(path: &Path, parameters: &str) -> Result<Option<SamplerState>, Box<dyn Error>> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let state: SamplerState = toml::from_str(&content)?;
+        if state.parameters != parameters {
+            info!(
+                state_file = path.to_str().unwrap(),
+                "Bar options have changed, starting over"
+            );
+            return Ok(None);
+        }
+        Ok(Some(state))
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        // via a Value so that tables are written after plain values
+        fs::write(path, toml::to_string(&toml::Value::try_from(self)?)?)?;
+        Ok(())
+    }
+}
+
+// The options that affect how bars of bar_type are sampled, which determine
+// where they're written and whether bars being appended to were sampled the
+// same way.
+fn parameters(opts: &BarOptions, bar_type: &str) -> String {
+    // only Display formats, which are kept stable, unlike Debug
+    let columns = opts
+        .columns
+        .iter()
+        .map(|column| column.name())
+        .collect::<Vec<&str>>()
+        .join(",");
+    let mut parameters = format!(
+        "{} profile=({}) multiply={} classification={} bvc_span={} columns={} \
+         timezone={} session={} drop_out_of_session={}",
+        bar_type,
+        opts.profile,
+        opts.multiply,
+        opts.classification,
+        opts.bvc_span,
+        columns,
+        opts.timezone.name(),
+        opts.session
+            .as_ref()
+            .map_or(String::from("none"), |session| session.to_string()),
+        opts.drop_out_of_session,
     );
-    writeln!(out_file, "date_time,open,high,low,close,volume,cum_dollars")?;
-    let tick_files = list_tick_files(in_dir_path)?;
-    for csv_file in tick_files {
-        let file = File::open(csv_file)?;
-        let mut rdr = csv::Reader::from_reader(file);
-        let mut tick = csv::ByteRecord::new();
-        while rdr.read_byte_record(&mut tick)? {
-            let date_time_str = String::from_utf8_lossy(&tick[1]).as_ref().to_owned();
-            let date_time = crate::iqfeed_date_time::parse(&date_time_str)?;
-            let minute = date_time.minute();
-            let last = String::from_utf8_lossy(&tick[2]).parse::<f64>()?;
-            if open == 0.0 {
-                open = last;
-                high = last;
-                low = last;
-                bartime = date_time;
-            }
-            let volume = String::from_utf8_lossy(&tick[3]).parse::<f64>()?;
-            cumulative_volume += volume;
-            cumulative_dollar += last * volume;
-            if last < low {
-                low = last;
-            }
-            if last > high {
-                high = last;
-            }
-            let close = last;
-            if minute % parsed_interval == 0 && minute != last_printed_minute {
-                writeln!(
-                    // TODO: fix timestamp, it should be open TS not close
-                    out_file,
-                    "{},{},{},{},{},{},{}",
-                    bartime
-                        .duration_round(Duration::minutes(15))?
-                        .format("%Y-%m-%d %H:%M:%S"),
-                    open,
-                    high,
-                    low,
-                    close,
-                    cumulative_volume,
-                    cumulative_dollar
-                )?;
-                open = 0.0;
-                high = 0.0;
-                low = 0.0;
-                cumulative_dollar = 0.0;
-                cumulative_volume = 0.0;
-                last_printed_minute = minute;
+    let specific = match bar_type {
+        "time" => format!("interval={}", opts.interval),
+        "dollar" => format!(
+            "dollar_threshold={} bars_per_day={} threshold_lookback_days={}",
+            opts.dollar_threshold,
+            opts.bars_per_day
+                .map_or(String::from("none"), |bars_per_day| bars_per_day
+                    .to_string()),
+            opts.threshold_lookback_days
+        ),
+        "tick" => format!("tick_threshold={}", opts.tick_threshold),
+        "volume" => format!("volume_threshold={}", opts.volume_threshold),
+        "range" => format!(
+            "tick_size={} range_ticks={}",
+            opts.tick_size, opts.range_ticks
+        ),
+        "renko" => format!("brick_size={}", opts.brick_size),
+        // imbalance and run bars
+        _ => {
+            // imbalance bars estimating E[b] per bar use expected_ticks_span
+            // for it instead
+            let imbalance_span =
+                if opts.expected_imbalance_per_bar && bar_type.ends_with("_imbalance") {
+                    String::from(" expected_imbalance_per_bar")
+                } else {
+                    format!(" expected_imbalance_span={}", opts.expected_imbalance_span)
+                };
+            let mut parameters = format!(
+                "tick_threshold={}{} expected_ticks_span={} warmup_bars={}",
+                opts.tick_threshold, imbalance_span, opts.expected_ticks_span, opts.warmup_bars
+            );
+            if opts.min_expected_ticks > 0. {
+                parameters.push_str(&format!(" min_expected_ticks={}", opts.min_expected_ticks));
+            }
+            if let Some(max_expected_ticks) = opts.max_expected_ticks {
+                parameters.push_str(&format!(" max_expected_ticks={}", max_expected_ticks));
+            }
+            parameters
+        }
+    };
+    parameters.push(' ');
+    parameters.push_str(&specific);
+    parameters
+}
+
+// 64 bit FNV-1a, which unlike std's hashers is guaranteed to stay the same
+// between releases, so output paths do too.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Where bars are written: opts.output with {symbol} replaced, or otherwise
+// bars/<symbol>/<bar_type>-<hash of parameters>.csv, so that running with the
+// same options always writes to the same file and bars sampled with different
+// options don't overwrite each other.
+fn output_path(opts: &BarOptions, bar_type: &str, parameters: &str) -> PathBuf {
+    match &opts.output {
+        Some(output) => PathBuf::from(output.replace("{symbol}", opts.symbol)),
+        None => Path::new("bars").join(opts.symbol).join(format!(
+            "{}-{:08x}.csv",
+            bar_type,
+            fnv1a(parameters) as u32
+        )),
+    }
+}
+
+// Written next to each bar file feat writes, e.g. dollar-0a1b2c3d.meta.toml
+// for dollar-0a1b2c3d.csv, to record how the bars were sampled. Only files
+// with one of these are ever removed by the retention policy.
+#[derive(Serialize, Deserialize)]
+struct BarFileMeta {
+    file: String,
+    symbol: String,
+    bar_type: String,
+    parameters: String,
+    written: DateTime<Utc>,
+}
+
+// How many of the bar files feat has written to keep around.
+pub struct Retention {
+    // bar files of the same type (but sampled with other options) to keep for
+    // each symbol, including the one just written
+    pub keep: Option<usize>,
+    // remove the symbol's bar files of any type that haven't been written to
+    // for longer
+    pub max_age: Option<Duration>,
+}
+
+// Metadata for each of the bar files feat has written in dir, newest first.
+fn read_bar_metas(dir: &Path) -> Result<Vec<(PathBuf, BarFileMeta)>, Box<dyn Error>> {
+    let mut metas = Vec::new();
+    for d in fs::read_dir(dir)?.flatten() {
+        let path = d.path();
+        if !path.to_str().unwrap_or("").ends_with(".meta.toml") {
+            continue;
+        }
+        let meta: BarFileMeta = match toml::from_str(&fs::read_to_string(&path)?) {
+            Ok(meta) => meta,
+            Err(err) => {
+                error!(
+                    meta_file = path.to_str().unwrap(),
+                    error = %err,
+                    "Couldn't read bar metadata"
+                );
+                continue;
+            }
+        };
+        metas.push((path, meta));
+    }
+    metas.sort_by_key(|(_, meta)| Reverse(meta.written));
+    Ok(metas)
+}
+
+// The bar file of bar_type most recently written for symbol in bars/<symbol>.
+pub fn latest_bar_file(symbol: &str, bar_type: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let dir = Path::new("bars").join(symbol);
+    read_bar_metas(&dir)?
+        .into_iter()
+        .find(|(_, meta)| meta.bar_type == bar_type)
+        .map(|(_, meta)| dir.join(meta.file))
+        .ok_or_else(|| format!("No {} bars for {} in {}", bar_type, symbol, dir.display()).into())
+}
+
+// Removes bar files for symbol in the same directory as out_path that the
+// retention policy doesn't keep, along with their metadata and incremental
+// state. Other symbols' files are left alone, since --output can put several
+// symbols in one directory.
+fn apply_retention(
+    out_path: &Path,
+    symbol: &str,
+    bar_type: &str,
+    retention: &Retention,
+) -> Result<(), Box<dyn Error>> {
+    if retention.keep.is_none() && retention.max_age.is_none() {
+        return Ok(());
+    }
+    let dir = match out_path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    // metadata names files relative to dir, so compare names rather than
+    // paths, which could be ./out.csv vs out.csv
+    let out_file = out_path.file_name().and_then(|name| name.to_str());
+    let metas = read_bar_metas(dir)?
+        .into_iter()
+        .filter(|(_, meta)| meta.symbol == symbol && Some(meta.file.as_str()) != out_file);
+    let mut n_same_type = 1;
+    for (meta_path, meta) in metas {
+        let mut expired = retention
+            .max_age
+            .is_some_and(|max_age| Utc::now() - meta.written > max_age);
+        if meta.bar_type == bar_type {
+            n_same_type += 1;
+            expired |= retention.keep.is_some_and(|keep| n_same_type > keep);
+        }
+        if expired {
+            let path = dir.join(&meta.file);
+            info!(file = path.to_str().unwrap(), "Removing old bars");
+            for path in [path.with_extension("state.toml"), path, meta_path].iter() {
+                match fs::remove_file(path) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
             }
         }
     }
     Ok(())
 }
 
-pub fn dollar_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
-    let (mut open, mut high, mut low, mut close, mut cumulative_dollar, mut cumulative_volume) =
-        (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
-    let mut bar_open_time = String::from("");
-    let mut prev_tick_timestamp = Vec::new();
-    let out_dir_path = Path::new("bars").join(opts.symbol);
-    let in_dir_path = Path::new("ticks").join(opts.symbol);
+// The bar in progress and everything else that carries over from one tick to
+// the next, and from one incremental run to the next.
+struct Progress<S> {
+    bar: Option<Bar>,
+    bar_boundary: Option<(DateTime<Tz>, bool)>,
+    prev_tick_timestamp: Vec<u8>,
+    // the bar in progress is complete, but isn't finished until a tick with a
+    // different timestamp comes in, since orders of huge size can come in as
+    // many ticks at exactly the same time and they all belong in one bar
+    sample_pending: bool,
+    classifier: Classifier,
+    sampler: S,
+}
+
+impl<S: Sampler> Progress<S> {
+    // Writes out the bar in progress, if there is one, and gets the sampler
+    // ready for the next one.
+    fn finish_bar(&mut self, out: &mut impl Write, opts: &BarOptions) -> std::io::Result<()> {
+        if let Some(bar) = self.bar.take() {
+            let buy_fraction = self.classifier.buy_fraction(bar.open, bar.close);
+            bar.write(
+                out,
+                buy_fraction,
+                &opts.columns,
+                &self.sampler.columns(&bar),
+            )?;
+            self.sampler.reset(&bar);
+        }
+        self.sample_pending = false;
+        Ok(())
+    }
+}
+
+// Adds each tick read by rdr to the bar in progress, writing bars out as the
+// sampler decides they're complete.
+fn sample_ticks<R: Read>(
+    opts: &BarOptions,
+    rdr: &mut csv::Reader<R>,
+    columns: &ColumnIndexes,
+    parse_date_time: bool,
+    boundaries: &mut Option<BoundaryCache>,
+    progress: &mut Progress<impl Sampler>,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn Error>> {
+    let mut tick = csv::ByteRecord::new();
+    while rdr.read_byte_record(&mut tick)? {
+        let timestamp = &tick[columns.timestamp];
+        let date_time = if parse_date_time {
+            match opts
+                .profile
+                .parse_timestamp(&String::from_utf8_lossy(timestamp))?
+            {
+                Some(date_time) => Some(date_time),
+                None => continue,
+            }
+        } else {
+            None
+        };
+        let mut boundary = None;
+        if let (Some(boundaries), Some(date_time)) = (boundaries.as_mut(), &date_time) {
+            let tick_boundary = boundaries.boundary(date_time);
+            if let Some((_, false)) = tick_boundary {
+                if opts.drop_out_of_session {
+                    continue;
+                }
+            }
+            // bars never span a session open or close
+            if tick_boundary != progress.bar_boundary {
+                progress.finish_bar(out, opts)?;
+                progress.bar_boundary = tick_boundary;
+            }
+            boundary = tick_boundary.map(|(boundary, _)| boundary);
+        }
+        let last = String::from_utf8_lossy(&tick[columns.price]).parse::<f64>()?;
+        let quote = parse_quote(&tick, columns);
+        let aggressor = parse_aggressor(&tick, columns.aggressor);
+        let parsed = Tick {
+            date_time,
+            last,
+            volume: String::from_utf8_lossy(&tick[columns.volume]).parse::<f64>()?,
+            boundary,
+            quote,
+            side: progress.classifier.side(last, quote, aggressor),
+        };
+        if progress.sample_pending && progress.prev_tick_timestamp != timestamp {
+            progress.finish_bar(out, opts)?;
+        }
+        if progress.sampler.starts_bar(&parsed) {
+            progress.finish_bar(out, opts)?;
+        }
+        if progress.bar.is_none() {
+            let open_time = match progress.sampler.open_time(&parsed) {
+                Some(open_time) => open_time,
+                None => tick_open_time(opts, timestamp, &parsed)?,
+            };
+            progress.bar = Some(Bar::new(open_time, parsed.last));
+        }
+        let cur = progress.bar.as_mut().unwrap();
+        cur.update(&parsed, opts.multiply);
+        progress.sampler.update(cur, &parsed);
+        if progress.sampler.should_sample(cur) {
+            progress.sample_pending = true;
+        }
+        progress.prev_tick_timestamp = timestamp.to_vec();
+    }
+    Ok(())
+}
+
+// Reads every tick file for opts.symbol and writes a bar each time the
+// sampler decides the bar in progress is complete.
+//
+// With opts.incremental, bars are appended to the output file and the partial
+// bar and state of the sampler are saved alongside it, so the next run picks
+// up where this one left off, from the first tick it hasn't seen yet.
+fn sample_bars(
+    opts: &BarOptions,
+    bar_type: &str,
+    sampler: impl Sampler,
+) -> Result<(), Box<dyn Error>> {
+    sample_bars_from(
+        Path::new("ticks").join(opts.symbol),
+        opts,
+        bar_type,
+        sampler,
+    )
+}
+
+// sample_bars with the tick files in in_dir_path.
+fn sample_bars_from(
+    in_dir_path: PathBuf,
+    opts: &BarOptions,
+    bar_type: &str,
+    sampler: impl Sampler,
+) -> Result<(), Box<dyn Error>> {
+    let mut boundaries = opts.session.as_ref().map(BoundaryCache::new);
+    // dropping ticks at DST changes means looking at every timestamp
+    let parse_date_time = opts.session.is_some()
+        || opts.profile.dst_policy() == DstPolicy::Drop
+        || sampler.needs_date_time()
+        || opts.columns.contains(&Column::TimeWeightedSpread);
+    let classifier = Classifier::new(opts.classification, opts.bvc_span);
+    if sampler.needs_trade_sides() && !classifier.classifies_trades() {
+        return Err(format!(
+            "{} bars can't be sampled with bulk volume classification",
+            bar_type
+        )
+        .into());
+    }
+    let mut progress = Progress {
+        bar: None,
+        bar_boundary: None,
+        prev_tick_timestamp: Vec::new(),
+        sample_pending: false,
+        classifier,
+        sampler,
+    };
+    let parameters = parameters(opts, bar_type);
+    let out_path = output_path(opts, bar_type, &parameters);
 
     info!(
-        out_dir_path = out_dir_path.to_str().unwrap(),
+        out_path = out_path.to_str().unwrap(),
         in_dir_path = in_dir_path.to_str().unwrap(),
         symbol = opts.symbol.as_str(),
         "Processing ticks into bars"
     );
-    fs::create_dir_all(out_dir_path.to_str().unwrap())?;
-    let now_dt = Utc::now().with_timezone(&New_York);
-    let file_name = format!("{}.csv", now_dt.format("dollar-%Y-%m-%d-%H-%M-%S"));
-    let out_path = out_dir_path.join(file_name);
-    let mut out_file = File::create(&out_path)?;
-    writeln!(out_file, "date_time,open,high,low,close,volume,cum_dollars")?;
+    if let Some(out_dir_path) = out_path.parent() {
+        fs::create_dir_all(out_dir_path)?;
+    }
+    let mut tick_files = list_tick_files(in_dir_path)?;
+    let state_path = out_path.with_extension("state.toml");
+    let meta_path = out_path.with_extension("meta.toml");
+    // bars are written elsewhere first, and only replace the bars file once
+    // the run succeeds, so that it's never seen half written
+    let tmp_path = out_path.with_extension("csv.tmp");
+    let mut offset = 0;
+    let state = if opts.incremental {
+        SamplerState::load(&state_path, &parameters)?
+    } else {
+        None
+    };
+    // the bars that the state goes with
+    let state = state.filter(|state| match fs::metadata(&out_path) {
+        Ok(metadata) if metadata.len() >= state.bars_length.unwrap_or(0) => true,
+        Ok(_) => {
+            info!(
+                out_path = out_path.to_str().unwrap(),
+                "Bars file is shorter than when it was last written, starting over"
+            );
+            false
+        }
+        Err(_) => false,
+    });
+    let state = state.and_then(|state| {
+        match tick_files
+            .iter()
+            .position(|f| f.file_name().unwrap().to_str() == Some(state.tick_file.as_str()))
+        {
+            Some(i) => {
+                tick_files.drain(..i);
+                Some(state)
+            }
+            None => {
+                info!(
+                    tick_file = state.tick_file.as_str(),
+                    "Last tick file processed is gone, starting over"
+                );
+                None
+            }
+        }
+    });
+    let mut out_file = match state {
+        Some(state) => {
+            offset = state.offset;
+            progress.bar_boundary = state
+                .bar_boundary
+                .map(|(boundary, in_session)| (boundary.with_timezone(&opts.timezone), in_session));
+            progress.prev_tick_timestamp = state.prev_tick_timestamp.into_bytes();
+            progress.sample_pending = state.sample_pending;
+            progress.bar = state.bar;
+            progress.classifier = state.classifier;
+            progress.sampler.restore(state.sampler)?;
+            fs::copy(&out_path, &tmp_path)?;
+            let out_file = OpenOptions::new().append(true).open(&tmp_path)?;
+            if let Some(bars_length) = state.bars_length {
+                out_file.set_len(bars_length)?;
+            }
+            BufWriter::new(out_file)
+        }
+        None => {
+            let mut out_file = BufWriter::new(File::create(&tmp_path)?);
+            writeln!(
+                out_file,
+                "{}{}",
+                header(&opts.columns),
+                progress.sampler.header()
+            )?;
+            out_file
+        }
+    };
     info!(
         out_file = out_path.to_str().unwrap(),
-        "Sampling dollar bars"
+        "Sampling {} bars", bar_type
     );
-    let tick_files = list_tick_files(in_dir_path)?;
+    let mut tick_file = None;
     for csv_file in tick_files {
         let file = File::open(&csv_file)?;
         let mut rdr = csv::ReaderBuilder::new()
-            .delimiter(opts.delimiter.as_bytes()[0])
+            .delimiter(opts.profile.delimiter())
+            .has_headers(opts.profile.headers())
             .from_reader(file);
-        let mut tick = csv::ByteRecord::new();
-        let mut new_bar = true;
-
-        while rdr.read_byte_record(&mut tick)? {
-            let last = String::from_utf8_lossy(&tick[opts.last_index]).parse::<f64>()?;
-            if new_bar {
-                bar_open_time = String::from_utf8_lossy(&tick[opts.timestamp_index]).to_string();
-                open = last;
-                high = last;
-                low = last;
-                cumulative_dollar = 0.0;
-                cumulative_volume = 0.0;
-                new_bar = false;
-            }
-            let volume = String::from_utf8_lossy(&tick[opts.volume_index]).parse::<f64>()?;
-            cumulative_volume += volume;
-            cumulative_dollar += last * volume * opts.multiply;
-            if last < low {
-                low = last;
-            }
-            if last > high {
-                high = last;
-            }
-            close = last;
-
-            // Need to check that open time is not the exact same as this tick's
-            // time, since sometimes orders of huge size come in at pretty much
-            // exactly the same time.
-            if cumulative_dollar >= opts.dollar_threshold
-                && prev_tick_timestamp != tick[opts.timestamp_index]
-            {
-                writeln!(
-                    out_file,
-                    "{},{},{},{},{},{},{}",
-                    bar_open_time, open, high, low, close, cumulative_volume, cumulative_dollar
-                )?;
-                new_bar = true;
-            }
-            prev_tick_timestamp = tick[opts.timestamp_index].to_vec();
+        let columns = if opts.profile.headers() {
+            opts.profile.indexes(rdr.headers()?)?
+        } else {
+            opts.profile.indexes(&csv::StringRecord::new())?
+        };
+        // only the first file can have been partly processed already
+        if offset > 0 {
+            let mut position = csv::Position::new();
+            position.set_byte(offset);
+            rdr.seek(position)?;
+            offset = 0;
         }
+        sample_ticks(
+            opts,
+            &mut rdr,
+            &columns,
+            parse_date_time,
+            &mut boundaries,
+            &mut progress,
+            &mut out_file,
+        )?;
+        tick_file = Some((csv_file, rdr.position().byte()));
     }
 
-    writeln!(
-        out_file,
-        "{},{},{},{},{},{},{}",
-        bar_open_time, open, high, low, close, cumulative_volume, cumulative_dollar
-    )?;
+    // the partial bar of an incremental run is finished off by the next run
+    // instead
+    if !opts.incremental {
+        progress.finish_bar(&mut out_file, opts)?;
+    }
+    out_file.flush()?;
+    drop(out_file);
+    let bars_length = fs::metadata(&tmp_path)?.len();
+    fs::rename(&tmp_path, &out_path)?;
+    // saved after the bars are in place, so a failure in between only means
+    // the new bars are sampled again
+    if opts.incremental {
+        if let Some((csv_file, offset)) = tick_file {
+            let state = SamplerState {
+                parameters: parameters.clone(),
+                tick_file: String::from(csv_file.file_name().unwrap().to_str().unwrap()),
+                offset,
+                bars_length: Some(bars_length),
+                bar_boundary: progress
+                    .bar_boundary
+                    .map(|(boundary, in_session)| (boundary.with_timezone(&Utc), in_session)),
+                prev_tick_timestamp: String::from_utf8(progress.prev_tick_timestamp)?,
+                sample_pending: progress.sample_pending,
+                bar: progress.bar,
+                classifier: progress.classifier,
+                sampler: progress.sampler.save()?,
+            };
+            state.save(&state_path)?;
+        }
+    } else {
+        // the partial bar from an earlier incremental run has just been
+        // written out as final, so it mustn't be resumed
+        match fs::remove_file(&state_path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    let meta = BarFileMeta {
+        file: String::from(out_path.file_name().unwrap().to_str().unwrap()),
+        symbol: opts.symbol.clone(),
+        bar_type: String::from(bar_type),
+        parameters,
+        written: Utc::now(),
+    };
+    fs::write(&meta_path, toml::to_string(&meta)?)?;
+    apply_retention(&out_path, opts.symbol, bar_type, &opts.retention)?;
+
+    Ok(())
+}
 
-    // clean up old bar files
-    for d in fs::read_dir(out_dir_path)?.flatten() {
-        if let Ok(metadata) = fs::metadata(d.path()) {
-            let modtime = metadata.modified().expect("Modtime supported");
-            let timedelta = Local::now() - DateTime::from(modtime);
-            if d.path() != out_path && timedelta > Duration::minutes(5) {
-                fs::remove_file(d.path())?;
+// Fixed dollar thresholds drift as prices and volumes change over the years,
+// so instead the threshold can be recomputed at the start of each day as the
+// average daily dollar volume of the trailing threshold_lookback_days days
+// divided by the target number of bars per day. The first day is left out,
+// since ticks usually start partway through it, and until a full day has been
+// seen, dollar_threshold is used.
+#[derive(Serialize, Deserialize)]
+struct AdaptiveDollarSampler {
+    bars_per_day: f64,
+    lookback_days: usize,
+    timezone: Tz,
+    threshold: f64,
+    day: Option<NaiveDate>,
+    // whether the ticks of the whole day have been seen, missing from state
+    // saved before the first day was left out
+    #[serde(default)]
+    day_complete: bool,
+    day_dollars: f64,
+    daily_dollars: VecDeque<f64>,
+    multiply: f64,
+}
+
+impl AdaptiveDollarSampler {
+    fn new(opts: &BarOptions, bars_per_day: f64) -> AdaptiveDollarSampler {
+        AdaptiveDollarSampler {
+            bars_per_day,
+            lookback_days: opts.threshold_lookback_days,
+            timezone: opts.timezone,
+            threshold: opts.dollar_threshold,
+            day: None,
+            day_complete: false,
+            day_dollars: 0.,
+            daily_dollars: VecDeque::new(),
+            multiply: opts.multiply,
+        }
+    }
+}
+
+impl Sampler for AdaptiveDollarSampler {
+    fn header(&self) -> &'static str {
+        ",threshold"
+    }
+
+    fn needs_date_time(&self) -> bool {
+        true
+    }
+
+    fn update(&mut self, _bar: &Bar, tick: &Tick) {
+        let day = tick
+            .date_time
+            .unwrap()
+            .with_timezone(&self.timezone)
+            .date_naive();
+        if self.day.is_some() && self.day != Some(day) {
+            if self.day_complete {
+                self.daily_dollars.push_back(self.day_dollars);
+                if self.daily_dollars.len() > self.lookback_days {
+                    self.daily_dollars.pop_front();
+                }
+                let average =
+                    self.daily_dollars.iter().sum::<f64>() / self.daily_dollars.len() as f64;
+                self.threshold = average / self.bars_per_day;
             }
+            self.day_complete = true;
+            self.day_dollars = 0.;
+        }
+        self.day = Some(day);
+        self.day_dollars += tick.last * tick.volume * self.multiply;
+    }
+
+    fn should_sample(&self, bar: &Bar) -> bool {
+        bar.dollars >= self.threshold
+    }
+
+    fn columns(&self, _bar: &Bar) -> String {
+        format!(",{}", self.threshold)
+    }
+
+    fn save(&self) -> Result<toml::Value, Box<dyn Error>> {
+        Ok(toml::Value::try_from(self)?)
+    }
+
+    fn restore(&mut self, state: toml::Value) -> Result<(), Box<dyn Error>> {
+        *self = state.try_into()?;
+        Ok(())
+    }
+}
+
+pub fn dollar_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    match opts.bars_per_day {
+        Some(bars_per_day) => sample_bars(
+            opts,
+            "dollar",
+            AdaptiveDollarSampler::new(opts, bars_per_day),
+        ),
+        None => sample_bars(opts, "dollar", |bar: &Bar| {
+            bar.dollars >= opts.dollar_threshold
+        }),
+    }
+}
+
+pub fn tick_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(opts, "tick", |bar: &Bar| bar.n_ticks >= opts.tick_threshold)
+}
+
+// Volume is scaled by opts.multiply so that a threshold can be given in terms
+// of the underlying (e.g. shares) rather than the number of contracts.
+pub fn volume_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(opts, "volume", |bar: &Bar| {
+        bar.volume * opts.multiply >= opts.volume_threshold
+    })
+}
+
+// Range bars are sampled once price has moved range_ticks ticks of tick_size
+// above or below the open of the bar. Moves are rounded to whole ticks so that
+// floating point error in prices doesn't matter.
+fn range_sampler<'a>(opts: &'a BarOptions) -> impl Fn(&Bar) -> bool + 'a {
+    move |bar: &Bar| {
+        let moved = (bar.high - bar.open).max(bar.open - bar.low);
+        (moved / opts.tick_size).round() as u64 >= opts.range_ticks
+    }
+}
+
+pub fn range_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(opts, "range", range_sampler(opts))
+}
+
+// Renko bars are sampled once price closes a brick_size above or below the
+// last brick. Bricks are anchored to the first tick and always move in whole
+// bricks, so unlike range bars, each bar is measured from where the last brick
+// ended rather than from the first tick of the bar.
+#[derive(Serialize, Deserialize)]
+struct RenkoSampler {
+    brick_size: f64,
+    brick: Option<f64>,
+}
+
+impl RenkoSampler {
+    // Whole bricks price has moved from the last brick, negative if down.
+    fn bricks(&self, last: f64) -> f64 {
+        match self.brick {
+            // a tiny bit of slack for floating point error in prices
+            Some(brick) => ((last - brick) / self.brick_size * (1. + 1e-9)).trunc(),
+            None => 0.,
+        }
+    }
+}
+
+impl Sampler for RenkoSampler {
+    fn update(&mut self, bar: &Bar, _tick: &Tick) {
+        if self.brick.is_none() {
+            self.brick = Some(bar.open);
+        }
+    }
+
+    fn should_sample(&self, bar: &Bar) -> bool {
+        self.bricks(bar.close) != 0.
+    }
+
+    fn reset(&mut self, bar: &Bar) {
+        let bricks = self.bricks(bar.close);
+        self.brick = self.brick.map(|brick| brick + bricks * self.brick_size);
+    }
+
+    fn save(&self) -> Result<toml::Value, Box<dyn Error>> {
+        Ok(toml::Value::try_from(self)?)
+    }
+
+    fn restore(&mut self, state: toml::Value) -> Result<(), Box<dyn Error>> {
+        *self = state.try_into()?;
+        Ok(())
+    }
+}
+
+pub fn renko_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(
+        opts,
+        "renko",
+        RenkoSampler {
+            brick_size: opts.brick_size,
+            brick: None,
+        },
+    )
+}
+
+// What each signed tick contributes to the imbalance or runs of a bar.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+enum TickValue {
+    Tick,
+    Volume,
+    Dollar,
+}
+
+impl TickValue {
+    fn of(self, tick: &Tick, multiply: f64) -> f64 {
+        match self {
+            TickValue::Tick => 1.,
+            TickValue::Volume => tick.volume,
+            TickValue::Dollar => tick.last * tick.volume * multiply,
+        }
+    }
+}
+
+// Clamps E[T] to the configured bounds. When order flow is balanced the
+// imbalance threshold keeps shrinking along with the bar length, so without a
+// lower bound imbalance and run bars can collapse to a tick or two each.
+fn bound_expected_ticks(expected_ticks: f64, min: f64, max: Option<f64>) -> f64 {
+    let expected_ticks = expected_ticks.max(min);
+    max.map_or(expected_ticks, |max| expected_ticks.min(max))
+}
+
+// Warns once when bars have become shorter on average than
+// min_expected_ticks (or two ticks), which bounding E[T] doesn't prevent when
+// the expected imbalance or runs are close to zero.
+fn warn_if_degenerate(warned: &mut bool, average: f64, min_expected_ticks: f64) {
+    if !*warned && average < min_expected_ticks.max(2.) {
+        warn!(
+            average_ticks = average,
+            "Bars have collapsed to a few ticks each, as the expected imbalance or \
+             runs are close to zero"
+        );
+        *warned = true;
+    }
+}
+
+// Imbalance bars as described in chapter 2 of Advances in Financial Machine
+// Learning, sampled the same way as mlfinlab's EMA imbalance bars. A bar is
+// sampled once the absolute signed imbalance of the bar exceeds E[T] * |E[b]|,
+// where E[T] is an EWMA of the lengths in ticks of the last
+// expected_ticks_span bars and E[b] is an EWMA of the signed imbalance of the
+// last expected_imbalance_span ticks, which is the side of the trade (see
+// Classifier::side) multiplied by 1, the volume or the dollar value of the
+// tick depending on the kind of imbalance. Both are only updated when a bar
+// is sampled. E[T] starts out at tick_threshold, and no bars are sampled
+// until there have been at least that many ticks to estimate E[b] from.
+//
+// When buys and sells are balanced E[b] hovers around zero and bars can
+// collapse to a tick or two each, so with per_bar E[b] is instead an EWMA of
+// the imbalance per tick of the last expected_ticks_span bars. A bar that
+// closes early on a small imbalance still has a large imbalance per tick,
+// which raises the next threshold.
+//
+// Until warmup_bars bars have been sampled, bars are sampled every E[T] ticks
+// instead.
+#[derive(Serialize, Deserialize)]
+struct ImbalanceSampler {
+    kind: TickValue,
+    multiply: f64,
+    per_bar: bool,
+    imbalance: f64,
+    // the imbalance of each tick, or with per_bar the imbalance per tick of
+    // each bar
+    imbalances: Window,
+    imbalance_span: f64,
+    n_ticks: u64,
+    expected_imbalance: Option<f64>,
+    bar_lengths: Window,
+    expected_ticks_span: f64,
+    initial_expected_ticks: f64,
+    min_expected_ticks: f64,
+    max_expected_ticks: Option<f64>,
+    warmup_bars: u64,
+    n_bars: u64,
+    #[serde(skip)]
+    warned: bool,
+}
+
+impl ImbalanceSampler {
+    fn new(opts: &BarOptions, kind: TickValue) -> ImbalanceSampler {
+        let per_bar = opts.expected_imbalance_per_bar;
+        let imbalance_span = if per_bar {
+            opts.expected_ticks_span
         } else {
-            error!("Couldn't delete bars, metadata not supported");
+            opts.expected_imbalance_span
+        };
+        ImbalanceSampler {
+            kind,
+            multiply: opts.multiply,
+            per_bar,
+            imbalance: 0.,
+            imbalances: Window::new(imbalance_span as usize),
+            imbalance_span,
+            n_ticks: 0,
+            expected_imbalance: None,
+            bar_lengths: Window::new(opts.expected_ticks_span as usize),
+            expected_ticks_span: opts.expected_ticks_span,
+            initial_expected_ticks: opts.tick_threshold as f64,
+            min_expected_ticks: opts.min_expected_ticks,
+            max_expected_ticks: opts.max_expected_ticks,
+            // E[b] per bar needs a bar to start from
+            warmup_bars: if per_bar {
+                opts.warmup_bars.max(1)
+            } else {
+                opts.warmup_bars
+            },
+            n_bars: 0,
+            warned: false,
         }
     }
 
-    Ok(())
+    fn expected_ticks(&self) -> f64 {
+        bound_expected_ticks(
+            self.bar_lengths
+                .ewm_mean(self.expected_ticks_span)
+                .unwrap_or(self.initial_expected_ticks),
+            self.min_expected_ticks,
+            self.max_expected_ticks,
+        )
+    }
+
+    // Like mlfinlab, E[b] per tick is left unknown, and so no more bars are
+    // sampled, while there are fewer ticks than E[T], and the span is the
+    // number of ticks when there are fewer than expected_imbalance_span.
+    fn update_expected_imbalance(&mut self) {
+        self.expected_imbalance = if self.per_bar {
+            self.imbalances.ewm_mean(self.imbalance_span)
+        } else if (self.n_ticks as f64) < self.expected_ticks() {
+            None
+        } else {
+            self.imbalances.ewm_mean(self.imbalances.len() as f64)
+        };
+    }
+
+    // The imbalance threshold in effect, or NaN during warmup.
+    fn threshold(&self) -> f64 {
+        if self.n_bars < self.warmup_bars {
+            return f64::NAN;
+        }
+        match self.expected_imbalance {
+            Some(expected_imbalance) => self.expected_ticks() * expected_imbalance.abs(),
+            None => f64::NAN,
+        }
+    }
+}
+
+impl Sampler for ImbalanceSampler {
+    fn header(&self) -> &'static str {
+        ",threshold"
+    }
+
+    fn needs_trade_sides(&self) -> bool {
+        true
+    }
+
+    fn update(&mut self, _bar: &Bar, tick: &Tick) {
+        let signed = tick.side * self.kind.of(tick, self.multiply);
+        self.imbalance += signed;
+        if !self.per_bar {
+            self.imbalances.push(signed);
+            self.n_ticks += 1;
+            if self.n_bars == 0 && self.expected_imbalance.is_none() {
+                self.update_expected_imbalance();
+            }
+        }
+    }
+
+    fn should_sample(&self, bar: &Bar) -> bool {
+        if self.n_bars < self.warmup_bars {
+            return bar.n_ticks as f64 >= self.expected_ticks();
+        }
+        // never true while the threshold is NaN
+        self.imbalance.abs() > self.threshold()
+    }
+
+    fn columns(&self, _bar: &Bar) -> String {
+        format!(",{}", self.threshold())
+    }
+
+    fn reset(&mut self, bar: &Bar) {
+        self.bar_lengths.push(bar.n_ticks as f64);
+        if self.per_bar {
+            self.imbalances.push(self.imbalance / bar.n_ticks as f64);
+        }
+        self.update_expected_imbalance();
+        self.imbalance = 0.;
+        self.n_bars += 1;
+        if self.n_bars > self.warmup_bars {
+            warn_if_degenerate(
+                &mut self.warned,
+                self.bar_lengths.ewm_mean(self.expected_ticks_span).unwrap(),
+                self.min_expected_ticks,
+            );
+        }
+    }
+
+    fn save(&self) -> Result<toml::Value, Box<dyn Error>> {
+        Ok(toml::Value::try_from(self)?)
+    }
+
+    fn restore(&mut self, state: toml::Value) -> Result<(), Box<dyn Error>> {
+        *self = state.try_into()?;
+        Ok(())
+    }
+}
+
+pub fn tick_imbalance_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(
+        opts,
+        "tick_imbalance",
+        ImbalanceSampler::new(opts, TickValue::Tick),
+    )
+}
+
+pub fn volume_imbalance_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(
+        opts,
+        "volume_imbalance",
+        ImbalanceSampler::new(opts, TickValue::Volume),
+    )
+}
+
+pub fn dollar_imbalance_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(
+        opts,
+        "dollar_imbalance",
+        ImbalanceSampler::new(opts, TickValue::Dollar),
+    )
+}
+
+// Run bars as described in chapter 2 of Advances in Financial Machine
+// Learning. Rather than letting buys and sells offset each other as imbalance
+// bars do, the buy and sell runs of a bar are accumulated separately and a bar
+// is sampled once the larger of the two exceeds
+// E[T] * max(P[b=1] * E[v|b=1], (1 - P[b=1]) * E[v|b=-1]), where P[b=1] is an
+// EWMA of the proportion of buys among the classified ticks of previous bars
+// and E[v|b] are EWMAs of the value of each buy or sell tick.
+//
+// Warmup works the same as for imbalance bars.
+#[derive(Serialize, Deserialize)]
+struct RunSampler {
+    kind: TickValue,
+    multiply: f64,
+    buy_run: f64,
+    sell_run: f64,
+    n_buys: u64,
+    // missing from state saved before unclassified ticks were left out of
+    // P[b=1]
+    #[serde(default)]
+    n_sells: u64,
+    expected_buy: Ewma,
+    expected_sell: Ewma,
+    expected_buy_proportion: Ewma,
+    expected_ticks: Ewma,
+    min_expected_ticks: f64,
+    max_expected_ticks: Option<f64>,
+    warmup_bars: u64,
+    n_bars: u64,
+    #[serde(skip)]
+    warned: bool,
+}
+
+impl RunSampler {
+    fn new(opts: &BarOptions, kind: TickValue) -> RunSampler {
+        RunSampler {
+            kind,
+            multiply: opts.multiply,
+            buy_run: 0.,
+            sell_run: 0.,
+            n_buys: 0,
+            n_sells: 0,
+            expected_buy: Ewma::new(opts.expected_imbalance_span),
+            expected_sell: Ewma::new(opts.expected_imbalance_span),
+            expected_buy_proportion: Ewma::with_initial(opts.expected_ticks_span, 0.5),
+            expected_ticks: Ewma::with_initial(
+                opts.expected_ticks_span,
+                opts.tick_threshold as f64,
+            ),
+            min_expected_ticks: opts.min_expected_ticks,
+            max_expected_ticks: opts.max_expected_ticks,
+            warmup_bars: opts.warmup_bars,
+            n_bars: 0,
+            warned: false,
+        }
+    }
+
+    fn expected_ticks(&self) -> f64 {
+        bound_expected_ticks(
+            self.expected_ticks.value().unwrap(),
+            self.min_expected_ticks,
+            self.max_expected_ticks,
+        )
+    }
+
+    // The run threshold in effect, or NaN during warmup.
+    fn threshold(&self) -> f64 {
+        if self.n_bars < self.warmup_bars {
+            return f64::NAN;
+        }
+        let p_buy = self.expected_buy_proportion.value().unwrap();
+        let expected_buy = self.expected_buy.value().unwrap_or(0.);
+        let expected_sell = self.expected_sell.value().unwrap_or(0.);
+        self.expected_ticks() * (p_buy * expected_buy).max((1. - p_buy) * expected_sell)
+    }
+}
+
+impl Sampler for RunSampler {
+    fn header(&self) -> &'static str {
+        ",buy_run,sell_run,threshold"
+    }
+
+    fn needs_trade_sides(&self) -> bool {
+        true
+    }
+
+    fn update(&mut self, _bar: &Bar, tick: &Tick) {
+        let value = self.kind.of(tick, self.multiply);
+        if tick.side > 0. {
+            self.buy_run += value;
+            self.n_buys += 1;
+            self.expected_buy.update(value);
+        } else if tick.side < 0. {
+            self.sell_run += value;
+            self.n_sells += 1;
+            self.expected_sell.update(value);
+        }
+    }
+
+    fn should_sample(&self, bar: &Bar) -> bool {
+        if self.n_bars < self.warmup_bars {
+            return bar.n_ticks as f64 >= self.expected_ticks();
+        }
+        self.buy_run.max(self.sell_run) >= self.threshold()
+    }
+
+    fn columns(&self, _bar: &Bar) -> String {
+        format!(",{},{},{}", self.buy_run, self.sell_run, self.threshold())
+    }
+
+    fn reset(&mut self, bar: &Bar) {
+        self.expected_ticks.update(bar.n_ticks as f64);
+        // ticks the classifier couldn't sign say nothing about P[b=1]
+        let n_classified = self.n_buys + self.n_sells;
+        if n_classified > 0 {
+            self.expected_buy_proportion
+                .update(self.n_buys as f64 / n_classified as f64);
+        }
+        self.buy_run = 0.;
+        self.sell_run = 0.;
+        self.n_buys = 0;
+        self.n_sells = 0;
+        self.n_bars += 1;
+        if self.n_bars > self.warmup_bars {
+            warn_if_degenerate(
+                &mut self.warned,
+                self.expected_ticks.value().unwrap(),
+                self.min_expected_ticks,
+            );
+        }
+    }
+
+    fn save(&self) -> Result<toml::Value, Box<dyn Error>> {
+        Ok(toml::Value::try_from(self)?)
+    }
+
+    fn restore(&mut self, state: toml::Value) -> Result<(), Box<dyn Error>> {
+        *self = state.try_into()?;
+        Ok(())
+    }
+}
+
+pub fn tick_run_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(opts, "tick_run", RunSampler::new(opts, TickValue::Tick))
+}
+
+pub fn volume_run_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(opts, "volume_run", RunSampler::new(opts, TickValue::Volume))
+}
+
+pub fn dollar_run_bars(opts: &BarOptions) -> Result<(), Box<dyn Error>> {
+    sample_bars(opts, "dollar_run", RunSampler::new(opts, TickValue::Dollar))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(symbol: &String) -> BarOptions<'_> {
+        BarOptions {
+            profile: Profile::iqfeed(),
+            multiply: 1.,
+            symbol,
+            classification: Classification::Tick,
+            bvc_span: 100.,
+            columns: Vec::new(),
+            incremental: false,
+            output: None,
+            retention: Retention {
+                keep: None,
+                max_age: None,
+            },
+            dollar_threshold: 7000000.,
+            tick_threshold: 3,
+            volume_threshold: 100000.,
+            expected_imbalance_span: 100.,
+            expected_imbalance_per_bar: false,
+            expected_ticks_span: 10.,
+            warmup_bars: 0,
+            min_expected_ticks: 0.,
+            max_expected_ticks: None,
+            interval: String::from("15m"),
+            timezone: chrono_tz::America::New_York,
+            session: None,
+            drop_out_of_session: false,
+            bars_per_day: None,
+            threshold_lookback_days: 20,
+            tick_size: 0.01,
+            range_ticks: 10,
+            brick_size: 1.,
+        }
+    }
+
+    // The bars sampled from ticks, given as date_time,last,last_size lines.
+    fn sample<S: Sampler>(opts: &BarOptions, sampler: S, ticks: &str) -> Vec<String> {
+        let ticks = format!("date_time,last,last_size\n{}", ticks);
+        let mut rdr = csv::Reader::from_reader(ticks.as_bytes());
+        let columns = opts.profile.indexes(rdr.headers().unwrap()).unwrap();
+        let mut progress = Progress {
+            bar: None,
+            bar_boundary: None,
+            prev_tick_timestamp: Vec::new(),
+            sample_pending: false,
+            classifier: Classifier::new(opts.classification, opts.bvc_span),
+            sampler,
+        };
+        let mut out = Vec::new();
+        sample_ticks(
+            opts,
+            &mut rdr,
+            &columns,
+            false,
+            &mut None,
+            &mut progress,
+            &mut out,
+        )
+        .unwrap();
+        progress.finish_bar(&mut out, opts).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn ticks_at_the_same_time_stay_in_one_bar() {
+        let symbol = String::from("TST");
+        let opts = options(&symbol);
+        let bars = sample(
+            &opts,
+            |bar: &Bar| bar.n_ticks >= opts.tick_threshold,
+            "2021-09-13 09:30:00.000001,10,1\n\
+             2021-09-13 09:30:00.000002,11,1\n\
+             2021-09-13 09:30:00.000003,12,1\n\
+             2021-09-13 09:30:00.000003,13,1\n\
+             2021-09-13 09:30:00.000003,14,1\n\
+             2021-09-13 09:30:00.000004,15,1\n",
+        );
+        assert_eq!(bars.len(), 2);
+        assert!(bars[0].starts_with("2021-09-13 09:30:00.000001000,10,14,10,14,5,"));
+        assert!(bars[1].starts_with("2021-09-13 09:30:00.000004000,15,15,15,15,1,"));
+    }
+
+    #[test]
+    fn buys_and_sells_add_up() {
+        let symbol = String::from("TST");
+        let ticks = "2021-09-13 09:30:00.000001,10,5\n\
+                     2021-09-13 09:30:00.000002,11,2\n\
+                     2021-09-13 09:30:00.000003,10.5,3\n\
+                     2021-09-13 09:30:00.000004,10.5,4\n\
+                     2021-09-13 09:30:00.000005,12,1\n\
+                     2021-09-13 09:30:00.000006,12,6\n";
+        let tick_bars = |opts: &BarOptions| {
+            sample(opts, |bar: &Bar| bar.n_ticks >= 3, ticks)
+                .iter()
+                .map(|bar| {
+                    bar.split(',')
+                        .skip(5)
+                        .take(6)
+                        .map(|x| x.parse::<f64>().unwrap())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        // volume, dollars, buy_volume, sell_volume, buy_dollars and
+        // sell_dollars, with the first trade left unclassified by the tick
+        // rule and the unchanged ones carrying the previous side
+        assert_eq!(
+            tick_bars(&options(&symbol)),
+            [
+                [10., 103.5, 2., 3., 22., 31.5],
+                [11., 126., 7., 4., 84., 42.]
+            ]
+        );
+        // bulk volume classification splits all of it
+        let opts = BarOptions {
+            classification: Classification::Bvc,
+            ..options(&symbol)
+        };
+        for bar in tick_bars(&opts) {
+            assert_eq!(bar[2] + bar[3], bar[0]);
+            assert_eq!(bar[4] + bar[5], bar[1]);
+        }
+    }
+
+    // Samples bars from ticks 0 to 20 in an incremental run, then from ticks
+    // 20 to 60 in another after adding them to the same and a new tick file,
+    // returning those bars and the bars from a full run over all of them.
+    fn resumed_and_full_runs<S: Sampler>(name: &str, sampler: impl Fn() -> S) -> (String, String) {
+        let dir = std::env::temp_dir().join(format!("feat-{}-{}", name, std::process::id()));
+        let ticks_dir = dir.join("ticks");
+        fs::create_dir_all(&ticks_dir).unwrap();
+        let ticks = |range: std::ops::Range<u32>| {
+            range
+                .map(|i| {
+                    format!(
+                        "2021-09-13 09:30:00.{:06},{},{}\n",
+                        i,
+                        10. + (i * 7 % 5) as f64 / 4.,
+                        1 + i % 3
+                    )
+                })
+                .collect::<String>()
+        };
+        let symbol = String::from("TST");
+        let out_path = dir.join("out.csv");
+        let opts = BarOptions {
+            incremental: true,
+            output: Some(String::from(out_path.to_str().unwrap())),
+            ..options(&symbol)
+        };
+        let run = |opts: &BarOptions| {
+            sample_bars_from(ticks_dir.clone(), opts, "test", sampler()).unwrap()
+        };
+
+        let first_file = ticks_dir.join("1.csv");
+        fs::write(
+            &first_file,
+            format!("date_time,last,last_size\n{}", ticks(0..20)),
+        )
+        .unwrap();
+        run(&opts);
+        // as if a later run had been interrupted while writing a bar
+        let mut out_file = OpenOptions::new().append(true).open(&out_path).unwrap();
+        write!(out_file, "2021-09-13 09:30:00.000020000,1").unwrap();
+        drop(out_file);
+        let mut first = OpenOptions::new().append(true).open(&first_file).unwrap();
+        write!(first, "{}", ticks(20..35)).unwrap();
+        drop(first);
+        fs::write(
+            ticks_dir.join("2.csv"),
+            format!("date_time,last,last_size\n{}", ticks(35..60)),
+        )
+        .unwrap();
+        run(&opts);
+        let resumed = fs::read_to_string(&out_path).unwrap();
+
+        let full_path = dir.join("full.csv");
+        run(&BarOptions {
+            incremental: false,
+            output: Some(String::from(full_path.to_str().unwrap())),
+            ..options(&symbol)
+        });
+        let full = fs::read_to_string(&full_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        (resumed, full)
+    }
+
+    #[test]
+    fn resumed_runs_match_a_full_run() {
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            tick_threshold: 5,
+            expected_imbalance_span: 10.,
+            expected_ticks_span: 3.,
+            ..options(&symbol)
+        };
+        let (resumed, full) = resumed_and_full_runs("resume-imbalance", || {
+            ImbalanceSampler::new(&opts, TickValue::Tick)
+        });
+        // the unfinished bar is left for the next incremental run
+        let full = full.lines().collect::<Vec<_>>();
+        assert!(full.len() > 4);
+        assert_eq!(resumed.lines().collect::<Vec<_>>(), full[..full.len() - 1]);
+        assert!(resumed.ends_with('\n'));
+
+        let (resumed, full) = resumed_and_full_runs("resume-tick", || |bar: &Bar| bar.n_ticks >= 4);
+        let full = full.lines().collect::<Vec<_>>();
+        assert_eq!(full.len(), 16);
+        assert_eq!(resumed.lines().collect::<Vec<_>>(), full[..full.len() - 1]);
+    }
+
+    #[test]
+    fn range_bars() {
+        let symbol = String::from("TST");
+        let opts = options(&symbol);
+        // 1.2 - 1.1 is a little under 0.1 in floating point
+        let bars = sample(
+            &opts,
+            range_sampler(&opts),
+            "2021-09-13 09:30:00.000001,1.1,1\n\
+             2021-09-13 09:30:00.000002,1.19,1\n\
+             2021-09-13 09:30:00.000003,1.2,1\n\
+             2021-09-13 09:30:00.000004,1.21,1\n\
+             2021-09-13 09:30:00.000005,1.3,1\n\
+             2021-09-13 09:30:00.000006,1.11,1\n\
+             2021-09-13 09:30:00.000007,1.12,1\n",
+        );
+        assert_eq!(bars.len(), 3);
+        assert!(bars[0].starts_with("2021-09-13 09:30:00.000001000,1.1,1.2,1.1,1.2,3,"));
+        assert!(bars[1].starts_with("2021-09-13 09:30:00.000004000,1.21,1.3,1.11,1.11,3,"));
+        assert!(bars[2].starts_with("2021-09-13 09:30:00.000007000,1.12,1.12,1.12,1.12,1,"));
+    }
+
+    #[test]
+    fn renko_bars() {
+        let symbol = String::from("TST");
+        let opts = options(&symbol);
+        let renko = RenkoSampler {
+            brick_size: opts.brick_size,
+            brick: None,
+        };
+        // a gap over three bricks closes one bar and moves the brick by all
+        // three, and a single brick down closes a bar too
+        let bars = sample(
+            &opts,
+            renko,
+            "2021-09-13 09:30:00.000001,10,1\n\
+             2021-09-13 09:30:00.000002,10.5,1\n\
+             2021-09-13 09:30:00.000003,13.2,1\n\
+             2021-09-13 09:30:00.000004,13.9,1\n\
+             2021-09-13 09:30:00.000005,14,1\n\
+             2021-09-13 09:30:00.000006,13.5,1\n\
+             2021-09-13 09:30:00.000007,13,1\n\
+             2021-09-13 09:30:00.000008,12.5,1\n",
+        );
+        assert_eq!(bars.len(), 4);
+        assert!(bars[0].starts_with("2021-09-13 09:30:00.000001000,10,13.2,10,13.2,3,"));
+        assert!(bars[1].starts_with("2021-09-13 09:30:00.000004000,13.9,14,13.9,14,2,"));
+        assert!(bars[2].starts_with("2021-09-13 09:30:00.000006000,13.5,13.5,13,13,2,"));
+        assert!(bars[3].starts_with("2021-09-13 09:30:00.000008000,12.5,12.5,12.5,12.5,1,"));
+    }
+
+    #[test]
+    fn adaptive_dollar_threshold_leaves_out_the_first_day() {
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            dollar_threshold: 1000.,
+            threshold_lookback_days: 2,
+            ..options(&symbol)
+        };
+        let mut sampler = AdaptiveDollarSampler::new(&opts, 10.);
+        let bar = Bar::new(String::new(), 100.);
+        let mut threshold = |date_time: &str, dollars: f64| {
+            let tick = Tick {
+                date_time: Some(new_york(date_time)),
+                last: 100.,
+                volume: dollars / 100.,
+                boundary: None,
+                quote: None,
+                side: 0.,
+            };
+            sampler.update(&bar, &tick);
+            sampler.threshold
+        };
+        // the ticks start in the afternoon
+        assert_eq!(threshold("2021-09-13 15:00:00.0", 2000.), 1000.);
+        assert_eq!(threshold("2021-09-14 09:30:00.0", 20000.), 1000.);
+        assert_eq!(threshold("2021-09-14 12:00:00.0", 20000.), 1000.);
+        assert_eq!(threshold("2021-09-15 09:30:00.0", 60000.), 4000.);
+        assert_eq!(threshold("2021-09-16 09:30:00.0", 10000.), 5000.);
+        // only the last two days
+        assert_eq!(threshold("2021-09-17 09:30:00.0", 10000.), 3500.);
+    }
+
+    // Writes a bar file with metadata and incremental state in dir, written
+    // age_days ago.
+    fn write_bar_file(dir: &Path, file: &str, symbol: &str, bar_type: &str, age_days: i64) {
+        let path = dir.join(file);
+        fs::write(&path, "date_time\n").unwrap();
+        fs::write(path.with_extension("state.toml"), "").unwrap();
+        let meta = BarFileMeta {
+            file: String::from(file),
+            symbol: String::from(symbol),
+            bar_type: String::from(bar_type),
+            parameters: String::new(),
+            written: Utc::now() - Duration::days(age_days),
+        };
+        fs::write(
+            path.with_extension("meta.toml"),
+            toml::to_string(&meta).unwrap(),
+        )
+        .unwrap();
+    }
+
+    // The files left in dir after applying retention for dollar bars of AAA
+    // written to out.csv, among other bars, the state and metadata of which
+    // go with them.
+    fn retained(name: &str, retention: Retention) -> Vec<String> {
+        let dir = std::env::temp_dir().join(format!("feat-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write_bar_file(&dir, "out.csv", "AAA", "dollar", 0);
+        write_bar_file(&dir, "dollar-1.csv", "AAA", "dollar", 1);
+        write_bar_file(&dir, "dollar-2.csv", "AAA", "dollar", 3);
+        write_bar_file(&dir, "tick-1.csv", "AAA", "tick", 2);
+        write_bar_file(&dir, "BBB-dollar.csv", "BBB", "dollar", 5);
+        // not written by feat
+        fs::write(dir.join("manual.csv"), "date_time\n").unwrap();
+        let res = apply_retention(&dir.join("out.csv"), "AAA", "dollar", &retention);
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|file| file.ends_with(".csv"))
+            .collect::<Vec<_>>();
+        // state and metadata are only left with their bars
+        for entry in fs::read_dir(&dir).unwrap() {
+            let file = entry.unwrap().file_name().into_string().unwrap();
+            let stem = file.split('.').next().unwrap();
+            assert!(dir.join(format!("{}.csv", stem)).exists());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+        res.unwrap();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn retention_only_removes_own_bars() {
+        let files = retained(
+            "retention-keep",
+            Retention {
+                keep: Some(2),
+                max_age: None,
+            },
+        );
+        assert_eq!(
+            files,
+            [
+                "BBB-dollar.csv",
+                "dollar-1.csv",
+                "manual.csv",
+                "out.csv",
+                "tick-1.csv"
+            ]
+        );
+        let files = retained(
+            "retention-max-age",
+            Retention {
+                keep: None,
+                max_age: Some(Duration::hours(36)),
+            },
+        );
+        assert_eq!(
+            files,
+            ["BBB-dollar.csv", "dollar-1.csv", "manual.csv", "out.csv"]
+        );
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(parse_interval("30s").unwrap(), Duration::seconds(30));
+        assert_eq!(parse_interval("5m").unwrap(), Duration::minutes(5));
+        assert_eq!(parse_interval("1h").unwrap(), Duration::hours(1));
+        assert_eq!(parse_interval("1d").unwrap(), Duration::days(1));
+        assert_eq!(parse_interval("15").unwrap(), Duration::minutes(15));
+        assert!(parse_interval("0m").is_err());
+        assert!(parse_interval("1w").is_err());
+        assert!(parse_interval("h").is_err());
+
+        let date_time = new_york("2021-09-13 09:47:31.5");
+        let starts = [("30s", "09:47:30"), ("5m", "09:45:00"), ("1h", "09:00:00")];
+        for (interval, start) in starts.iter() {
+            let start = format!("2021-09-13 {}.000000000", start);
+            let interval = parse_interval(interval).unwrap();
+            assert_eq!(
+                interval_start(date_time, interval)
+                    .format(crate::iqfeed_date_time::FORMAT)
+                    .to_string(),
+                start
+            );
+        }
+        assert_eq!(
+            interval_start(date_time, Duration::days(1)),
+            new_york("2021-09-13 00:00:00.0")
+        );
+    }
+
+    fn new_york(s: &str) -> DateTime<Tz> {
+        crate::timestamp::parse_local(s, chrono_tz::America::New_York).unwrap()
+    }
+
+    // The open times of the time bars started by ticks every half hour from
+    // the given time, written in New York time.
+    fn time_bar_opens(interval: &str, from: DateTime<Tz>, n_ticks: i64) -> Vec<String> {
+        let mut sampler = TimeSampler {
+            interval_ms: parse_interval(interval).unwrap().num_milliseconds(),
+            timezone: chrono_tz::America::New_York,
+            start: None,
+        };
+        let mut opens = Vec::new();
+        let mut starts = Vec::new();
+        for i in 0..n_ticks {
+            let tick = Tick {
+                date_time: Some(from + Duration::minutes(30 * i)),
+                last: 100.,
+                volume: 1.,
+                boundary: None,
+                quote: None,
+                side: 0.,
+            };
+            if sampler.starts_bar(&tick) {
+                opens.push(sampler.open_time(&tick).unwrap()[11..16].to_string());
+                starts.push(sampler.start.unwrap());
+            }
+        }
+        assert!(starts.windows(2).all(|w| w[0] < w[1]));
+        opens
+    }
+
+    #[test]
+    fn time_bars_across_dst_changes() {
+        // clocks went forward from 02:00 EST to 03:00 EDT
+        let midnight = new_york("2021-03-14 00:00:00.0");
+        assert_eq!(
+            time_bar_opens("1h", midnight, 8),
+            ["00:00", "01:00", "03:00", "04:00"]
+        );
+        assert_eq!(
+            time_bar_opens("90m", midnight, 8),
+            ["00:00", "01:30", "03:00", "04:30"]
+        );
+        // and back from 02:00 EDT to 01:00 EST, so the bar that opened at
+        // 01:30 EDT lasts until 01:30 EST
+        let midnight = new_york("2021-11-07 00:00:00.0");
+        assert_eq!(
+            time_bar_opens("1h", midnight, 8),
+            ["00:00", "01:00", "01:00", "02:00"]
+        );
+        assert_eq!(
+            time_bar_opens("90m", midnight, 10),
+            ["00:00", "01:30", "01:30", "03:00"]
+        );
+    }
+
+    #[test]
+    fn repeated_bar_times_read_back_as_the_first() {
+        // bars are stamped without an offset, so the bars opening at 01:00
+        // EDT and 01:00 EST read back as the same time
+        let mut sampler = TimeSampler {
+            interval_ms: parse_interval("1h").unwrap().num_milliseconds(),
+            timezone: chrono_tz::America::New_York,
+            start: None,
+        };
+        let edt = new_york("2021-11-07 01:00:00.0");
+        let est = edt + Duration::hours(1);
+        let opens = [edt, est]
+            .iter()
+            .map(|date_time| {
+                let tick = Tick {
+                    date_time: Some(*date_time),
+                    last: 100.,
+                    volume: 1.,
+                    boundary: None,
+                    quote: None,
+                    side: 0.,
+                };
+                assert!(sampler.starts_bar(&tick));
+                sampler.open_time(&tick).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(opens[0], "2021-11-07 01:00:00.000000000");
+        assert_eq!(opens[1], opens[0]);
+        let read_back = crate::timestamp::parse_local(&opens[1], chrono_tz::America::New_York);
+        assert_eq!(read_back.unwrap(), edt);
+    }
+
+    // Adds ticks on the given sides to bars as sample_ticks does, returning
+    // the number of ticks in each bar sampled and the threshold it was
+    // sampled at.
+    fn sample_sides(sampler: &mut impl Sampler, sides: &[f64]) -> Vec<(u64, String)> {
+        let mut bar: Option<Bar> = None;
+        let mut bars = Vec::new();
+        for side in sides {
+            let tick = Tick {
+                date_time: None,
+                last: 100.,
+                volume: 1.,
+                boundary: None,
+                quote: None,
+                side: *side,
+            };
+            let cur = bar.get_or_insert_with(|| Bar::new(String::new(), tick.last));
+            cur.update(&tick, 1.);
+            sampler.update(cur, &tick);
+            if sampler.should_sample(cur) {
+                bars.push((cur.n_ticks, sampler.columns(cur)));
+                sampler.reset(cur);
+                bar = None;
+            }
+        }
+        bars
+    }
+
+    #[test]
+    fn expected_ticks_bounds() {
+        assert_eq!(bound_expected_ticks(3., 5., None), 5.);
+        assert_eq!(bound_expected_ticks(7., 5., Some(8.)), 7.);
+        assert_eq!(bound_expected_ticks(10., 5., Some(8.)), 8.);
+        assert_eq!(bound_expected_ticks(10., 0., None), 10.);
+    }
+
+    // The thresholds written in the columns of bars sampled by sample_sides.
+    fn thresholds(bars: &[(u64, String)]) -> Vec<f64> {
+        bars.iter()
+            .map(|(_, columns)| columns.rsplit(',').next().unwrap().parse().unwrap())
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn imbalance_threshold() {
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            tick_threshold: 4,
+            // alphas of 0.5, and windows of 3 bars and 3 ticks
+            expected_ticks_span: 3.,
+            expected_imbalance_span: 3.,
+            ..options(&symbol)
+        };
+        let mut sampler = ImbalanceSampler::new(&opts, TickValue::Tick);
+        let bars = sample_sides(
+            &mut sampler,
+            &[
+                // nothing is sampled until there are tick_threshold ticks,
+                // when E[b] is (1 - 0.5 + 0.25) / 1.75 over the last 3 ticks
+                1., 1., -1., 1., //
+                // E[b] is unchanged until a bar is sampled
+                1., 1., //
+                // then E[T] is (2 + 0.5 * 4) / 1.5 and E[b] is 1
+                -1., -1., -1., //
+            ],
+        );
+        assert_eq!(
+            bars.iter().map(|(n_ticks, _)| *n_ticks).collect::<Vec<_>>(),
+            [4, 2, 3]
+        );
+        let thresholds = thresholds(&bars);
+        assert_close(thresholds[0], 4. * 3. / 7.);
+        assert_close(thresholds[1], 4. * 3. / 7.);
+        assert_close(thresholds[2], 8. / 3.);
+    }
+
+    #[test]
+    fn imbalance_threshold_per_bar() {
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            tick_threshold: 4,
+            warmup_bars: 2,
+            expected_imbalance_per_bar: true,
+            // alpha of 0.5 and a window of 3 bars
+            expected_ticks_span: 3.,
+            ..options(&symbol)
+        };
+        let mut sampler = ImbalanceSampler::new(&opts, TickValue::Tick);
+        let bars = sample_sides(
+            &mut sampler,
+            &[
+                // warmup bars of tick_threshold ticks, after which E[T] is 4
+                // and E[b] is (1 + 0.5 * 0.5) / 1.5
+                1., 1., 1., -1., //
+                1., 1., 1., 1., //
+                // sampled once the imbalance exceeds 4 * 5 / 6
+                1., -1., 1., 1., 1., 1., //
+                // E[T] is (6 + 2 + 1) / 1.75 and E[b] is
+                // (4 / 6 + 0.5 + 0.25 * 0.5) / 1.75
+                -1., -1., -1., -1., //
+            ],
+        );
+        assert_eq!(
+            bars.iter().map(|(n_ticks, _)| *n_ticks).collect::<Vec<_>>(),
+            [4, 4, 6, 4]
+        );
+        let thresholds = thresholds(&bars);
+        assert!(thresholds[0].is_nan() && thresholds[1].is_nan());
+        assert_close(thresholds[2], 10. / 3.);
+        assert_close(thresholds[3], 36. / 7. * 31. / 42.);
+    }
+
+    #[test]
+    fn imbalance_warmup_is_bounded() {
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            tick_threshold: 4,
+            warmup_bars: 2,
+            min_expected_ticks: 6.,
+            ..options(&symbol)
+        };
+        let mut sampler = ImbalanceSampler::new(&opts, TickValue::Tick);
+        let bars = sample_sides(&mut sampler, &[1.; 12]);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].0, 6);
+        assert_eq!(bars[1].0, 6);
+    }
+
+    // The same bars as mlfinlab's EMA imbalance bars, i.e.
+    // get_ema_{tick,volume,dollar}_imbalance_bars with num_prev_bars=3,
+    // expected_imbalance_window=50 and exp_num_ticks_init=10, for a random
+    // walk of 200 ticks. The expected bars come from a line by line port of
+    // EMAImbalanceBars from mlfinlab 1.x to plain Python. The last bar
+    // written here is the unfinished one, which mlfinlab leaves out.
+    #[test]
+    fn imbalance_bars_match_mlfinlab() {
+        let mut ticks = String::new();
+        let mut x: u64 = 1;
+        let mut cents: i64 = 200;
+        for i in 0..200 {
+            x = (x * 1103515245 + 12345) % (1 << 31);
+            cents += (x >> 16) as i64 % 9 - 4;
+            ticks.push_str(&format!(
+                "2021-09-13 09:30:00.{:06},{},{}\n",
+                i,
+                cents as f64 / 100.,
+                1 + (x >> 8) % 100
+            ));
+        }
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            tick_threshold: 10,
+            expected_ticks_span: 3.,
+            expected_imbalance_span: 50.,
+            ..options(&symbol)
+        };
+        // the first tick, close and volume of each bar
+        let expected = [
+            (
+                TickValue::Tick,
+                vec![(0, 1.87, 1004.), (23, 1.87, 2657.), (77, 1.74, 4482.)],
+            ),
+            (TickValue::Volume, vec![(0, 1.97, 610.), (11, 1.85, 2901.)]),
+            (TickValue::Dollar, vec![(0, 1.97, 610.), (11, 1.85, 2929.)]),
+        ];
+        for (kind, expected) in expected.iter() {
+            let bars = sample(&opts, ImbalanceSampler::new(&opts, *kind), &ticks);
+            assert_eq!(bars.len(), expected.len() + 1);
+            let bars = bars[..expected.len()]
+                .iter()
+                .map(|bar| {
+                    let fields = bar.split(',').collect::<Vec<_>>();
+                    (
+                        fields[0][20..].parse::<u64>().unwrap() / 1000,
+                        fields[4].parse::<f64>().unwrap(),
+                        fields[5].parse::<f64>().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(&bars, expected);
+        }
+    }
+
+    #[test]
+    fn run_threshold() {
+        let symbol = String::from("TST");
+        let opts = BarOptions {
+            tick_threshold: 4,
+            warmup_bars: 2,
+            // alphas of 0.5
+            expected_ticks_span: 3.,
+            expected_imbalance_span: 3.,
+            ..options(&symbol)
+        };
+        let mut sampler = RunSampler::new(&opts, TickValue::Tick);
+        let bars = sample_sides(
+            &mut sampler,
+            &[
+                // warmup bars, with unclassified ticks left out of P[b=1]
+                1., 1., 0., -1., //
+                1., 0., 0., 1., //
+                // sampled once the buy run reaches E[T] * P[b=1]
+                1., -1., 1., 0., 1., 1., //
+            ],
+        );
+        let p_buy = 0.5 * 1. + 0.5 * (0.5 * (2. / 3.) + 0.5 * 0.5);
+        assert_eq!(bars.len(), 3);
+        assert_eq!((bars[0].0, bars[1].0, bars[2].0), (4, 4, 6));
+        assert_eq!(bars[0].1, ",2,1,NaN");
+        let threshold = bars[2]
+            .1
+            .rsplit(',')
+            .next()
+            .unwrap()
+            .parse::<f64>()
+            .unwrap();
+        assert!((threshold - 4. * p_buy).abs() < 1e-12);
+        assert!(bars[2].1.starts_with(",4,1,"));
+    }
 }
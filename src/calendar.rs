@@ -0,0 +1,189 @@
+use crate::session::Session;
+use chrono::{Datelike, NaiveDate, NaiveTime, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+// Which days an exchange trades, and the sessions it trades during them.
+#[derive(Debug, Clone)]
+pub struct Calendar {
+    pub timezone: Tz,
+    weekdays: Vec<Weekday>,
+    holidays: HashSet<NaiveDate>,
+    early_closes: HashMap<NaiveDate, NaiveTime>,
+    sessions: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CalendarFile {
+    timezone: Option<String>,
+    weekdays: Option<Vec<String>>,
+    #[serde(default)]
+    holidays: Vec<NaiveDate>,
+    #[serde(default)]
+    early_closes: HashMap<NaiveDate, String>,
+    #[serde(default)]
+    sessions: HashMap<String, Vec<String>>,
+}
+
+const WEEKDAYS: [Weekday; 5] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+];
+
+impl Calendar {
+    // Trades every weekday in timezone, with no holidays or sessions.
+    pub fn weekdays(timezone: Tz) -> Calendar {
+        Calendar {
+            timezone,
+            weekdays: WEEKDAYS.to_vec(),
+            holidays: HashSet::new(),
+            early_closes: HashMap::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    // Built in calendars. These don't know about any holidays, which are
+    // loaded from calendars.toml.
+    fn builtin(name: &str) -> Option<Calendar> {
+        let (mut calendar, sessions) = match name {
+            "nyse" => (
+                Calendar::weekdays(chrono_tz::America::New_York),
+                vec![("rth", "09:30-16:00"), ("eth", "04:00-20:00")],
+            ),
+            // sessions wrapping past midnight belong to the day they close on,
+            // so Sunday evening's open is part of Monday
+            "cme" => (
+                Calendar::weekdays(chrono_tz::America::Chicago),
+                vec![("globex", "17:00-16:00")],
+            ),
+            "crypto" => (
+                Calendar {
+                    weekdays: WEEKDAYS
+                        .iter()
+                        .chain([Weekday::Sat, Weekday::Sun].iter())
+                        .cloned()
+                        .collect(),
+                    ..Calendar::weekdays(chrono_tz::UTC)
+                },
+                vec![("crypto", "00:00-00:00")],
+            ),
+            _ => return None,
+        };
+        for (session, window) in sessions {
+            calendar
+                .sessions
+                .insert(String::from(session), vec![String::from(window)]);
+        }
+        Some(calendar)
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        self.weekdays.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    pub fn early_close(&self, date: NaiveDate) -> Option<NaiveTime> {
+        self.early_closes.get(&date).cloned()
+    }
+}
+
+fn parse_weekday(s: &str) -> Result<Weekday, Box<dyn Error>> {
+    s.parse::<Weekday>()
+        .map_err(|_| format!("Invalid weekday {:?}", s).into())
+}
+
+// Calendars by name, e.g.
+//
+// [nyse]
+// holidays = ["2021-11-25", "2021-12-24"]
+//
+// [nyse.early_closes]
+// "2021-11-26" = "13:00"
+//
+// Entries for the built in calendars (nyse, cme and crypto) add to them, any
+// other entries need to have a timezone and at least one session.
+pub struct Calendars {
+    calendars: HashMap<String, Calendar>,
+}
+
+impl Calendars {
+    // Loads calendars from path, which doesn't need to exist.
+    pub fn load(path: &str) -> Result<Calendars, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Calendars::parse(&content),
+            Err(err) if err.kind() == ErrorKind::NotFound => Calendars::parse(""),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Calendars, Box<dyn Error>> {
+        let files: HashMap<String, CalendarFile> = toml::from_str(content)?;
+        let mut calendars = HashMap::new();
+        for name in &["nyse", "cme", "crypto"] {
+            calendars.insert(String::from(*name), Calendar::builtin(name).unwrap());
+        }
+        for (name, file) in files {
+            let mut calendar = match (calendars.remove(&name), &file.timezone) {
+                (Some(calendar), None) => calendar,
+                (Some(calendar), Some(timezone)) => Calendar {
+                    timezone: timezone.parse::<Tz>()?,
+                    ..calendar
+                },
+                (None, Some(timezone)) => Calendar::weekdays(timezone.parse::<Tz>()?),
+                (None, None) => {
+                    return Err(format!("Calendar {} needs a timezone", name).into());
+                }
+            };
+            if let Some(weekdays) = &file.weekdays {
+                let weekdays: Result<Vec<Weekday>, Box<dyn Error>> =
+                    weekdays.iter().map(|d| parse_weekday(d)).collect();
+                calendar.weekdays = weekdays?;
+            }
+            calendar.holidays.extend(file.holidays);
+            for (date, time) in file.early_closes {
+                calendar
+                    .early_closes
+                    .insert(date, NaiveTime::parse_from_str(&time, "%H:%M")?);
+            }
+            calendar.sessions.extend(file.sessions);
+            calendars.insert(name, calendar);
+        }
+        Ok(Calendars { calendars })
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Calendar, Box<dyn Error>> {
+        self.calendars
+            .get(name)
+            .ok_or_else(|| format!("Unknown calendar {:?}", name).into())
+    }
+
+    // Resolves a session by name, either qualified with its calendar (e.g.
+    // nyse:rth) or not (e.g. rth), falling back to a comma separated list of
+    // windows on weekdays in timezone, e.g. 09:30-12:00,13:00-16:00
+    pub fn session(&self, spec: &str, timezone: Tz) -> Result<Session, Box<dyn Error>> {
+        if let Some((calendar, session)) = spec.split_once(':') {
+            if let Ok(calendar) = self.get(calendar) {
+                return match calendar.sessions.get(session) {
+                    Some(windows) => Session::new(calendar.clone(), windows),
+                    None => Err(format!("Unknown session {:?}", spec).into()),
+                };
+            }
+        }
+        let mut names = self.calendars.keys().collect::<Vec<&String>>();
+        names.sort();
+        for name in names {
+            let calendar = &self.calendars[name];
+            if let Some(windows) = calendar.sessions.get(spec) {
+                return Session::new(calendar.clone(), windows);
+            }
+        }
+        let windows = spec.split(',').map(String::from).collect::<Vec<String>>();
+        Session::new(Calendar::weekdays(timezone), &windows)
+    }
+}
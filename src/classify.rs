@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::stats::{normal_cdf, EwmStd};
+
+// How trades are classified as buys or sells.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Classification {
+    // The aggressor IQFeed reports for the trade, falling back to Lee-Ready
+    // for trades without one.
+    Aggressor,
+    Tick,
+    Quote,
+    LeeReady,
+    // Bulk volume classification, which splits the volume of a whole bar
+    // rather than classifying individual trades.
+    Bvc,
+}
+
+impl FromStr for Classification {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Classification, Box<dyn Error>> {
+        match s {
+            "aggressor" => Ok(Classification::Aggressor),
+            "tick" => Ok(Classification::Tick),
+            "quote" => Ok(Classification::Quote),
+            "lee_ready" => Ok(Classification::LeeReady),
+            "bvc" => Ok(Classification::Bvc),
+            _ => Err(format!("Unknown trade classification {:?}", s).into()),
+        }
+    }
+}
+
+// The same names FromStr takes.
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Classification::Aggressor => write!(f, "aggressor"),
+            Classification::Tick => write!(f, "tick"),
+            Classification::Quote => write!(f, "quote"),
+            Classification::LeeReady => write!(f, "lee_ready"),
+            Classification::Bvc => write!(f, "bvc"),
+        }
+    }
+}
+
+// Signs trades by the tick rule: a trade is a buy (1) if it printed above the
+// previous trade, a sell (-1) if below, and carries the previous sign if the
+// price is unchanged.
+#[derive(Serialize, Deserialize)]
+struct TickRule {
+    prev_last: Option<f64>,
+    sign: f64,
+}
+
+impl TickRule {
+    fn new() -> TickRule {
+        TickRule {
+            prev_last: None,
+            sign: 0.,
+        }
+    }
+
+    fn sign(&mut self, last: f64) -> f64 {
+        if let Some(prev_last) = self.prev_last {
+            if last > prev_last {
+                self.sign = 1.;
+            } else if last < prev_last {
+                self.sign = -1.;
+            }
+        }
+        self.prev_last = Some(last);
+        self.sign
+    }
+}
+
+// Signs trades by the quote rule: a trade is a buy if it printed above the
+// midpoint of the bid and ask, a sell if below, and unclassified (0) at the
+// midpoint or without a quote.
+fn quote_sign(last: f64, quote: Option<(f64, f64)>) -> f64 {
+    match quote {
+        Some((bid, ask)) => {
+            let mid = (bid + ask) / 2.;
+            if last > mid {
+                1.
+            } else if last < mid {
+                -1.
+            } else {
+                0.
+            }
+        }
+        None => 0.,
+    }
+}
+
+// Classifies trades as buys (1), sells (-1) or unclassified (0).
+//
+// Lee-Ready uses the quote rule, and the tick rule for trades at the midpoint
+// or without a quote. Lee and Ready compared trades to quotes from 5 seconds
+// earlier to make up for trades being reported late, but the bid and ask in a
+// tick are the quote at the time of the trade, so they're used as is.
+#[derive(Serialize, Deserialize)]
+pub struct Classifier {
+    classification: Classification,
+    tick_rule: TickRule,
+    price_changes: EwmStd,
+    prev_close: Option<f64>,
+}
+
+impl Classifier {
+    // bvc_span is the span of the EWM standard deviation of price changes
+    // between bars used by bulk volume classification.
+    pub fn new(classification: Classification, bvc_span: f64) -> Classifier {
+        Classifier {
+            classification,
+            tick_rule: TickRule::new(),
+            price_changes: EwmStd::new(bvc_span),
+            prev_close: None,
+        }
+    }
+
+    // Whether trades are classified individually by side, rather than per
+    // bar by buy_fraction.
+    pub fn classifies_trades(&self) -> bool {
+        self.classification != Classification::Bvc
+    }
+
+    // quote is the bid and ask at the time of the trade. aggressor is the
+    // IQFeed trade aggressor, 1 for buys and 2 for sells, with anything else
+    // (0 is invalid and 3 is neither) counting as unknown.
+    pub fn side(&mut self, last: f64, quote: Option<(f64, f64)>, aggressor: Option<u32>) -> f64 {
+        // always updated, so it's ready whenever it's needed as a fallback
+        let tick_sign = self.tick_rule.sign(last);
+        let quote_sign = quote_sign(last, quote);
+        let lee_ready = if quote_sign != 0. {
+            quote_sign
+        } else {
+            tick_sign
+        };
+        match self.classification {
+            Classification::Aggressor => match aggressor {
+                Some(1) => 1.,
+                Some(2) => -1.,
+                _ => lee_ready,
+            },
+            Classification::Tick => tick_sign,
+            Classification::Quote => quote_sign,
+            Classification::LeeReady => lee_ready,
+            Classification::Bvc => 0.,
+        }
+    }
+
+    // Bulk volume classification, as described by Easley, Lopez de Prado and
+    // O'Hara in Flow Toxicity and Liquidity in a High Frequency World. The
+    // fraction of a bar's volume that was bought is Z(dP / sigma), where Z is
+    // the standard normal CDF, dP is the change in price from the last bar's
+    // close and sigma is the standard deviation of the changes of previous
+    // bars. Returns None unless classifying by bvc.
+    pub fn buy_fraction(&mut self, open: f64, close: f64) -> Option<f64> {
+        if self.classifies_trades() {
+            return None;
+        }
+        let change = close - self.prev_close.unwrap_or(open);
+        self.prev_close = Some(close);
+        let fraction = match self.price_changes.value() {
+            Some(sigma) if self.price_changes.len() > 1 && sigma > 0. => normal_cdf(change / sigma),
+            // not enough history yet to say which way the volume went
+            _ => 0.5,
+        };
+        self.price_changes.update(change);
+        Some(fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sides() {
+        // last, bid and ask, and aggressor
+        let ticks = [
+            // nothing to go on for the first trade, which is at the midpoint
+            (10., Some((9.75, 10.25)), None),
+            // an uptick at the midpoint
+            (10.25, Some((10., 10.5)), Some(1)),
+            // unchanged, so the tick rule carries the uptick forward
+            (10.25, Some((10., 10.25)), Some(3)),
+            (10., Some((10., 10.5)), Some(2)),
+            // without a quote
+            (10., None, None),
+            // an uptick at the bid, where Lee-Ready goes by the quote
+            (10.5, Some((10.5, 11.)), Some(0)),
+            // a buy at the bid
+            (10.5, Some((10.5, 11.)), Some(1)),
+        ];
+        let expected = [
+            (Classification::Tick, [0., 1., 1., -1., -1., 1., 1.]),
+            (Classification::Quote, [0., 0., 1., -1., 0., -1., -1.]),
+            (Classification::LeeReady, [0., 1., 1., -1., -1., -1., -1.]),
+            (Classification::Aggressor, [0., 1., 1., -1., -1., -1., 1.]),
+            (Classification::Bvc, [0.; 7]),
+        ];
+        for (classification, sides) in expected.iter() {
+            let mut classifier = Classifier::new(*classification, 100.);
+            let actual = ticks
+                .iter()
+                .map(|(last, quote, aggressor)| classifier.side(*last, *quote, *aggressor))
+                .collect::<Vec<_>>();
+            assert_eq!(actual, sides, "{}", classification);
+        }
+    }
+
+    #[test]
+    fn bvc_buy_fraction() {
+        assert_eq!(
+            Classifier::new(Classification::Tick, 3.).buy_fraction(10., 11.),
+            None
+        );
+        // alpha of 0.5
+        let mut classifier = Classifier::new(Classification::Bvc, 3.);
+        // half and half until there are two price changes to go on, after
+        // which the standard deviation of the changes is 1
+        assert_eq!(classifier.buy_fraction(10., 11.), Some(0.5));
+        assert_eq!(classifier.buy_fraction(11., 10.), Some(0.5));
+        // changes are from the previous close rather than the open
+        let fraction = classifier.buy_fraction(10.5, 11.).unwrap();
+        assert!((fraction - 0.8413447461).abs() < 1e-7);
+    }
+
+    #[test]
+    fn normal_cdf_values() {
+        let values = [
+            (0., 0.5),
+            (1., 0.8413447461),
+            (-1., 0.1586552539),
+            (-1.96, 0.0249978951),
+            (3., 0.9986501020),
+        ];
+        for (x, cdf) in values.iter() {
+            assert!((normal_cdf(*x) - cdf).abs() < 1e-7, "normal_cdf({})", x);
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::profile::{ColumnRef, Profile};
+use crate::stats::EwmStd;
+use chrono::{DateTime, Duration};
+use chrono_tz::Tz;
+use std::error::Error;
+use std::io::Write;
+
+pub struct CusumOptions<'o> {
+    pub input_file: &'o str,
+    pub timestamp_column: ColumnRef,
+    pub price_column: ColumnRef,
+    // how to read the input and parse the timestamps for the daily volatility
+    pub profile: Profile,
+    pub threshold: f64,
+    // if set, the threshold is this multiple of the daily volatility instead
+    pub vol_multiple: Option<f64>,
+    pub vol_span: usize,
+    // what event timestamps are written in, as bars are
+    pub timezone: Tz,
+}
+
+// Daily volatility, the EW standard deviation of returns between
+// observations more than a day apart, as `feat vol` measures days. Nothing is
+// returned until span days have been seen.
+pub struct DailyVol {
+    day_start: Option<(DateTime<Tz>, f64)>,
+    std: EwmStd,
+    span: usize,
+}
+
+impl DailyVol {
+    pub fn new(span: usize) -> DailyVol {
+        DailyVol {
+            day_start: None,
+            std: EwmStd::new(span as f64),
+            span,
+        }
+    }
+
+    pub fn update(&mut self, date_time: DateTime<Tz>, price: f64) {
+        match self.day_start {
+            Some((day_start, day_price)) => {
+                if date_time.signed_duration_since(day_start) > Duration::days(1) {
+                    self.std.update((price / day_price) - 1.);
+                    self.day_start = Some((date_time, price));
+                }
+            }
+            None => self.day_start = Some((date_time, price)),
+        }
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        if self.std.len() < self.span {
+            return None;
+        }
+        self.std.value()
+    }
+}
+
+// Symmetric CUSUM filter, as in chapter 2 of Advances in Financial Machine
+// Learning. Log returns are accumulated into separate positive and negative
+// sums, and an event is emitted (and the sum reset) whenever one of them
+// exceeds the threshold.
+struct CusumFilter {
+    s_pos: f64,
+    s_neg: f64,
+    prev_price: Option<f64>,
+}
+
+impl CusumFilter {
+    fn new() -> CusumFilter {
+        CusumFilter {
+            s_pos: 0.,
+            s_neg: 0.,
+            prev_price: None,
+        }
+    }
+
+    // The side of the event at price, if any: 1 for an upward move and -1
+    // for a downward one. Without a threshold nothing is accumulated.
+    fn update(&mut self, price: f64, threshold: Option<f64>) -> Option<f64> {
+        let prev_price = self.prev_price.replace(price)?;
+        let threshold = threshold?;
+        let ret = (price / prev_price).ln();
+        self.s_pos = (self.s_pos + ret).max(0.);
+        self.s_neg = (self.s_neg + ret).min(0.);
+        if self.s_neg < -threshold {
+            self.s_neg = 0.;
+            Some(-1.)
+        } else if self.s_pos > threshold {
+            self.s_pos = 0.;
+            Some(1.)
+        } else {
+            None
+        }
+    }
+}
+
+// Runs a CUSUM filter over the input, printing events with the side of the
+// move that triggered them and the threshold in effect. Event timestamps are
+// written in the same format and timezone as bars, so they can be read back
+// alongside them.
+pub fn cusum(opts: &CusumOptions) -> Result<(), Box<dyn Error>> {
+    let stdout = std::io::stdout();
+    write_events(opts, &mut stdout.lock())
+}
+
+fn write_events(opts: &CusumOptions, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(opts.profile.delimiter())
+        .has_headers(opts.profile.headers())
+        .from_path(opts.input_file)?;
+    let headers = if opts.profile.headers() {
+        rdr.headers()?.clone()
+    } else {
+        csv::StringRecord::new()
+    };
+    let timestamp_index = opts.timestamp_column.index(&headers)?;
+    let price_index = opts.price_column.index(&headers)?;
+    let mut daily_vol = DailyVol::new(opts.vol_span);
+    let mut filter = CusumFilter::new();
+    writeln!(out, "date_time,side,threshold")?;
+
+    let mut record = csv::StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        // rows with timestamps dropped by the DST policy are skipped
+        let date_time = match opts.profile.parse_timestamp(&record[timestamp_index])? {
+            Some(date_time) => date_time,
+            None => continue,
+        };
+        let price = record[price_index].parse::<f64>()?;
+        let threshold = match opts.vol_multiple {
+            Some(vol_multiple) => {
+                daily_vol.update(date_time, price);
+                daily_vol.value().map(|vol| vol * vol_multiple)
+            }
+            None => Some(opts.threshold),
+        };
+        if let Some(side) = filter.update(price, threshold) {
+            writeln!(
+                out,
+                "{},{},{}",
+                date_time
+                    .with_timezone(&opts.timezone)
+                    .format(crate::iqfeed_date_time::FORMAT),
+                side,
+                threshold.unwrap()
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::{parse_local, Timestamp};
+    use chrono_tz::America::New_York;
+
+    fn new_york(s: &str) -> DateTime<Tz> {
+        parse_local(&format!("{}.0", s), New_York).unwrap()
+    }
+
+    #[test]
+    fn cusum_filter_is_symmetric() {
+        let mut filter = CusumFilter::new();
+        let mut price = 100.;
+        assert_eq!(filter.update(price, Some(0.01)), None);
+        let mut sides = Vec::new();
+        // log returns of 0.006, which take two steps to exceed the threshold
+        // either way, with the sums starting again from 0 after each event
+        for ret in [0.006, 0.006, 0.006, -0.006, -0.006, -0.006, 0.006, 0.006].iter() {
+            price *= f64::exp(*ret);
+            sides.push(filter.update(price, Some(0.01)));
+        }
+        assert_eq!(
+            sides,
+            [None, Some(1.), None, None, Some(-1.), None, None, Some(1.)]
+        );
+    }
+
+    #[test]
+    fn cusum_filter_waits_for_a_threshold() {
+        let mut filter = CusumFilter::new();
+        assert_eq!(filter.update(100., None), None);
+        assert_eq!(filter.update(110., None), None);
+        // only the return from 110 counts
+        assert_eq!(filter.update(111., Some(0.01)), None);
+        assert_eq!(filter.update(113., Some(0.01)), Some(1.));
+    }
+
+    #[test]
+    fn daily_vol_waits_for_span_days() {
+        let mut daily_vol = DailyVol::new(2);
+        daily_vol.update(new_york("2021-09-13 10:00:00"), 100.);
+        // less than a day later
+        daily_vol.update(new_york("2021-09-13 16:00:00"), 120.);
+        daily_vol.update(new_york("2021-09-14 10:01:00"), 110.);
+        assert_eq!(daily_vol.value(), None);
+        daily_vol.update(new_york("2021-09-15 10:02:00"), 99.);
+        // the EW standard deviation of 0.1 and -0.1 with an alpha of 2/3
+        let vol = daily_vol.value().unwrap();
+        assert!((vol - 2f64.sqrt() / 3. * 0.2).abs() < 1e-12);
+    }
+
+    fn events(name: &str, input: &str, opts: CusumOptions) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("feat-{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, input).unwrap();
+        let opts = CusumOptions {
+            input_file: path.to_str().unwrap(),
+            ..opts
+        };
+        let mut out = Vec::new();
+        let res = write_events(&opts, &mut out);
+        std::fs::remove_file(&path).unwrap();
+        res.unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    fn options() -> CusumOptions<'static> {
+        CusumOptions {
+            input_file: "",
+            timestamp_column: ColumnRef::Name(String::from("date_time")),
+            price_column: ColumnRef::Name(String::from("close")),
+            profile: Profile::iqfeed(),
+            threshold: 0.01,
+            vol_multiple: None,
+            vol_span: 2,
+            timezone: New_York,
+        }
+    }
+
+    #[test]
+    fn threshold_from_daily_vol() {
+        let events = events(
+            "cusum-vol",
+            "date_time,close\n\
+             2021-09-13 10:00:00.000000,100\n\
+             2021-09-14 10:01:00.000000,110\n\
+             2021-09-15 10:02:00.000000,99\n\
+             2021-09-15 10:03:00.000000,110\n",
+            CusumOptions {
+                vol_multiple: Some(1.),
+                ..options()
+            },
+        );
+        let threshold = 2f64.sqrt() / 3. * (99. / 110. - 1.1f64).abs();
+        assert_eq!(
+            events,
+            [
+                String::from("date_time,side,threshold"),
+                format!("2021-09-15 10:02:00.000000000,-1,{}", threshold),
+                format!("2021-09-15 10:03:00.000000000,1,{}", threshold),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_are_written_as_bars_are() {
+        let events = events(
+            "cusum-timestamps",
+            "time,price\n\
+             1631541600,100\n\
+             1631541601,102\n",
+            CusumOptions {
+                timestamp_column: ColumnRef::Name(String::from("time")),
+                price_column: ColumnRef::Name(String::from("price")),
+                profile: Profile {
+                    timestamp_type: Some(Timestamp::Seconds),
+                    timezone: Some(Tz::UTC),
+                    ..Profile::iqfeed()
+                },
+                ..options()
+            },
+        );
+        assert_eq!(events[1], "2021-09-13 10:00:01.000000000,1,0.01");
+        let date_time = events[1].split(',').next().unwrap();
+        assert_eq!(
+            parse_local(date_time, New_York).unwrap(),
+            new_york("2021-09-13 10:00:01")
+        );
+    }
+}
@@ -1,24 +1,45 @@
-use chrono::offset::TimeZone;
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use chrono_tz::America::New_York;
 use chrono_tz::Tz;
 use serde::de::{self};
 use serde::{Deserialize, Deserializer, Serializer};
 use std::error::Error;
 
+use crate::timestamp::{localize, DstPolicy};
+
+// always nine fractional digits, so nanoseconds survive a round trip
 pub const FORMAT: &str = "%Y-%m-%d %H:%M:%S.%f";
 
+// Parses IQFeed's timestamps, which are in New York time.
 pub fn parse(s: &str) -> Result<DateTime<Tz>, Box<dyn Error>> {
-    let year = s[..4].parse::<i32>()?;
-    let month = s[5..7].parse::<u32>()?;
-    let day = s[8..10].parse::<u32>()?;
-    let hour = s[11..13].parse::<u32>()?;
-    let minute = s[14..16].parse::<u32>()?;
-    let second = s[17..19].parse::<u32>()?;
-    let milli = s[20..23].parse::<u32>()?;
-    Ok(New_York
-        .ymd(year, month, day)
-        .and_hms_milli(hour, minute, second, milli))
+    localize(&parse_naive(s)?, New_York, DstPolicy::Error)?
+        .ok_or_else(|| format!("Invalid IQFeed timestamp {:?}", s).into())
+}
+
+pub fn parse_naive(s: &str) -> Result<NaiveDateTime, Box<dyn Error>> {
+    // short or empty values are an error rather than a panic
+    let field = |start: usize, end: usize| {
+        s.get(start..end)
+            .ok_or_else(|| format!("Invalid IQFeed timestamp {:?}", s))
+    };
+    let year = field(0, 4)?.parse::<i32>()?;
+    let month = field(5, 7)?.parse::<u32>()?;
+    let day = field(8, 10)?.parse::<u32>()?;
+    let hour = field(11, 13)?.parse::<u32>()?;
+    let minute = field(14, 16)?.parse::<u32>()?;
+    let second = field(17, 19)?.parse::<u32>()?;
+    // any number of fractional digits, kept to the nanosecond
+    let fraction = s.get(20..).unwrap_or("");
+    let mut nano = 0;
+    for (i, digit) in fraction.chars().enumerate() {
+        let digit = digit.to_digit(10).ok_or("invalid fractional seconds")?;
+        if i < 9 {
+            nano += digit * 10u32.pow(8 - i as u32);
+        }
+    }
+    NaiveDate::from_ymd_opt(year, month, day)
+        .and_then(|d| d.and_hms_nano_opt(hour, minute, second, nano))
+        .ok_or_else(|| "invalid date time".into())
 }
 
 pub fn serialize<S>(date: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error>
@@ -36,3 +57,41 @@ where
     let s = String::deserialize(deserializer)?;
     parse(&s).map_err(de::Error::custom)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    #[test]
+    fn fractional_seconds() {
+        let nanos = |s: &str| parse_naive(s).unwrap().nanosecond();
+        let fractions = [
+            ("", 0),
+            (".", 0),
+            (".1", 100000000),
+            (".12", 120000000),
+            (".123", 123000000),
+            (".1234", 123400000),
+            (".12345", 123450000),
+            (".123456", 123456000),
+            (".1234567", 123456700),
+            (".12345678", 123456780),
+            (".123456789", 123456789),
+            // digits past the nanosecond are dropped
+            (".123456789123", 123456789),
+        ];
+        for (fraction, expected) in fractions.iter() {
+            assert_eq!(
+                nanos(&format!("2021-09-13 09:30:00{}", fraction)),
+                *expected
+            );
+        }
+        // IQFeed's microseconds
+        assert_eq!(nanos("2021-09-13 09:30:00.000001"), 1000);
+        assert_eq!(nanos("2021-09-13 09:30:00.500000"), 500000000);
+        assert_eq!(nanos("2021-09-13 09:30:00.000000001"), 1);
+        assert!(parse_naive("2021-09-13 09:30:00.12x").is_err());
+        assert!(parse_naive("2021-09-13 09:30").is_err());
+    }
+}
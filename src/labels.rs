@@ -0,0 +1,1081 @@
+use chrono::{DateTime, Duration};
+use chrono_tz::Tz;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::bars::list_tick_files;
+use crate::events::DailyVol;
+use crate::profile::{column_index, Profile};
+use crate::timestamp::parse_local;
+
+pub struct TripleBarrierOptions<'o> {
+    pub symbol: &'o str,
+    pub bars_file: PathBuf,
+    pub events_file: &'o str,
+    pub profile: Profile,
+    // what the timestamps of bars and events are local to
+    pub timezone: Tz,
+    // the upper and lower barriers are these multiples of the daily
+    // volatility away from the entry price, or not used if 0
+    pub profit_take: f64,
+    pub stop_loss: f64,
+    pub vertical_barrier: Duration,
+    pub vol_span: usize,
+    // primary model side predictions to meta-label the events with, see
+    // read_events
+    pub sides_file: Option<&'o str>,
+    // columns of the bar each event falls in to print with its label
+    pub bar_columns: Vec<String>,
+}
+
+struct Event {
+    // as given, so that labels line up with events
+    date_time: String,
+    parsed: DateTime<Tz>,
+    // 1 for long and -1 for short, if meta-labeling
+    side: Option<f64>,
+}
+
+// Timestamps of the events in events_file, from its date_time column, in
+// order.
+//
+// With sides_file, a CSV of date_time and side (1 or -1) predicted by a
+// primary model, each event takes the latest side predicted at or before it.
+// Events without one, or with a side of 0, are left out.
+fn read_events(
+    events_file: &str,
+    sides_file: Option<&str>,
+    timezone: Tz,
+) -> Result<Vec<Event>, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_path(events_file)?;
+    let date_time_index = column_index(rdr.headers()?, "date_time")?;
+    let mut events = Vec::new();
+    for record in rdr.records() {
+        let date_time = String::from(&record?[date_time_index]);
+        let parsed = parse_local(&date_time, timezone)?;
+        events.push(Event {
+            date_time,
+            parsed,
+            side: None,
+        });
+    }
+    events.sort_by_key(|event| event.parsed);
+
+    if let Some(sides_file) = sides_file {
+        let mut rdr = csv::Reader::from_path(sides_file)?;
+        let headers = rdr.headers()?.clone();
+        let date_time_index = column_index(&headers, "date_time")?;
+        let side_index = column_index(&headers, "side")?;
+        let mut sides = Vec::new();
+        for record in rdr.records() {
+            let record = record?;
+            let date_time = parse_local(&record[date_time_index], timezone)?;
+            let side = record[side_index].parse::<f64>()?;
+            sides.push((date_time, Some(side.signum()).filter(|_| side != 0.)));
+        }
+        sides.sort_by_key(|(date_time, _)| *date_time);
+        let mut sides = sides.into_iter().peekable();
+        let mut side = None;
+        for event in events.iter_mut() {
+            while let Some((_, next_side)) =
+                sides.next_if(|(date_time, _)| *date_time <= event.parsed)
+            {
+                side = next_side;
+            }
+            event.side = side;
+        }
+        let n_events = events.len();
+        events.retain(|event| event.side.is_some());
+        if events.len() < n_events {
+            info!(
+                n_events = n_events - events.len(),
+                "Leaving out events without a side"
+            );
+        }
+    }
+    Ok(events)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Barrier {
+    ProfitTake,
+    StopLoss,
+    Vertical,
+}
+
+impl Barrier {
+    fn name(&self) -> &'static str {
+        match self {
+            Barrier::ProfitTake => "profit_take",
+            Barrier::StopLoss => "stop_loss",
+            Barrier::Vertical => "vertical",
+        }
+    }
+}
+
+// An event that's waiting for one of its barriers to be touched.
+struct Position {
+    event: usize,
+    entry_time: DateTime<Tz>,
+    entry_price: f64,
+    // the profit take is below the entry price when short
+    side: f64,
+    upper: Option<f64>,
+    lower: Option<f64>,
+    end: DateTime<Tz>,
+    // daily volatility the barriers are scaled by
+    target: f64,
+    last: f64,
+    // opts.bar_columns of the bar the event fell in
+    bar_columns: Vec<String>,
+}
+
+struct Touch {
+    date_time: DateTime<Tz>,
+    price: f64,
+    barrier: Barrier,
+}
+
+// Finds where each event in the bars enters. An event is taken to happen at
+// the close of the bar it falls in, so it enters at that bar's close as of the
+// open of the next bar. The vertical barrier is counted from entry, since a
+// long bar or a gap before the next bar could otherwise end it before the
+// position was ever held. Events without a daily volatility yet, or without a
+// bar after them, are left out.
+fn positions(
+    opts: &TripleBarrierOptions,
+    events: &[Event],
+) -> Result<VecDeque<Position>, Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_path(&opts.bars_file)?;
+    let headers = rdr.headers()?.clone();
+    let date_time_index = column_index(&headers, "date_time")?;
+    let close_index = column_index(&headers, "close")?;
+    let bar_column_indexes = opts
+        .bar_columns
+        .iter()
+        .map(|column| column_index(&headers, column))
+        .collect::<Result<Vec<usize>, Box<dyn Error>>>()?;
+    let mut daily_vol = DailyVol::new(opts.vol_span);
+    let mut positions = VecDeque::new();
+    let mut prev: Option<(f64, csv::StringRecord)> = None;
+    let mut next_event = 0;
+    let mut n_before_bars = 0;
+    let mut n_without_vol = 0;
+    for record in rdr.records() {
+        let record = record?;
+        let date_time = parse_local(&record[date_time_index], opts.timezone)?;
+        let close = record[close_index].parse::<f64>()?;
+        while next_event < events.len() && events[next_event].parsed < date_time {
+            if prev.is_none() {
+                n_before_bars += 1;
+            } else if daily_vol.value().is_none() {
+                n_without_vol += 1;
+            }
+            if let (Some((entry_price, prev_record)), Some(vol)) = (&prev, daily_vol.value()) {
+                let entry_price = *entry_price;
+                let side = events[next_event].side.unwrap_or(1.);
+                let (up, down) = if side < 0. {
+                    (opts.stop_loss, opts.profit_take)
+                } else {
+                    (opts.profit_take, opts.stop_loss)
+                };
+                let barrier = |multiple: f64| Some(multiple * vol).filter(|width| *width > 0.);
+                positions.push_back(Position {
+                    event: next_event,
+                    entry_time: date_time,
+                    entry_price,
+                    side,
+                    upper: barrier(up).map(|width| entry_price * (1. + width)),
+                    lower: barrier(down).map(|width| entry_price * (1. - width)),
+                    end: date_time + opts.vertical_barrier,
+                    target: vol,
+                    last: entry_price,
+                    bar_columns: bar_column_indexes
+                        .iter()
+                        .map(|i| String::from(&prev_record[*i]))
+                        .collect(),
+                });
+            }
+            next_event += 1;
+        }
+        daily_vol.update(date_time, close);
+        prev = Some((close, record));
+    }
+    let skipped = [
+        (n_before_bars, "before the first bar"),
+        (
+            n_without_vol,
+            "without vol_span days of bars for the daily volatility",
+        ),
+        (
+            events.len() - next_event,
+            "without a bar after them to enter at",
+        ),
+    ];
+    for (n_events, reason) in skipped.iter() {
+        if *n_events > 0 {
+            info!(n_events, "Leaving out events {}", reason);
+        }
+    }
+    Ok(positions)
+}
+
+// Checks each position's barriers against the ticks after its entry.
+struct Labeler {
+    // positions that haven't entered yet, in order of entry
+    pending: VecDeque<Position>,
+    active: Vec<Position>,
+    // by event
+    touches: Vec<Option<(Position, Touch)>>,
+}
+
+impl Labeler {
+    fn new(pending: VecDeque<Position>, n_events: usize) -> Labeler {
+        Labeler {
+            pending,
+            active: Vec::new(),
+            touches: (0..n_events).map(|_| None).collect(),
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pending.is_empty() && self.active.is_empty()
+    }
+
+    fn tick(&mut self, date_time: DateTime<Tz>, last: f64) {
+        // positions whose vertical barrier passed before this tick exit at the
+        // last price before it
+        let mut i = 0;
+        while i < self.active.len() {
+            if self.active[i].end < date_time {
+                let position = self.active.swap_remove(i);
+                let event = position.event;
+                let touch = Touch {
+                    date_time: position.end,
+                    price: position.last,
+                    barrier: Barrier::Vertical,
+                };
+                self.touches[event] = Some((position, touch));
+            } else {
+                i += 1;
+            }
+        }
+        // positions whose vertical barrier passed before their first tick
+        // never saw a price after entry
+        while self
+            .pending
+            .front()
+            .is_some_and(|position| position.entry_time <= date_time)
+        {
+            let position = self.pending.pop_front().unwrap();
+            if position.end < date_time {
+                let event = position.event;
+                let touch = Touch {
+                    date_time: position.end,
+                    price: position.entry_price,
+                    barrier: Barrier::Vertical,
+                };
+                self.touches[event] = Some((position, touch));
+            } else {
+                self.active.push(position);
+            }
+        }
+        let mut i = 0;
+        while i < self.active.len() {
+            let position = &mut self.active[i];
+            let (up, down) = if position.side < 0. {
+                (Barrier::StopLoss, Barrier::ProfitTake)
+            } else {
+                (Barrier::ProfitTake, Barrier::StopLoss)
+            };
+            let barrier = if position.upper.is_some_and(|upper| last >= upper) {
+                Some(up)
+            } else if position.lower.is_some_and(|lower| last <= lower) {
+                Some(down)
+            } else {
+                None
+            };
+            match barrier {
+                Some(barrier) => {
+                    let position = self.active.swap_remove(i);
+                    let event = position.event;
+                    let touch = Touch {
+                        date_time,
+                        price: last,
+                        barrier,
+                    };
+                    self.touches[event] = Some((position, touch));
+                }
+                None => {
+                    position.last = last;
+                    i += 1;
+                }
+            }
+        }
+    }
+}
+
+// The return from entry to a touch and its label, as printed by
+// triple_barrier.
+fn return_and_label(position: &Position, touch: &Touch, meta: bool) -> (f64, i32) {
+    let ret = touch.price / position.entry_price - 1.;
+    if meta {
+        let ret = position.side * ret;
+        return (ret, if ret > 0. { 1 } else { 0 });
+    }
+    let label = match touch.barrier {
+        Barrier::ProfitTake => 1,
+        Barrier::StopLoss => -1,
+        Barrier::Vertical if ret > 0. => 1,
+        Barrier::Vertical if ret < 0. => -1,
+        Barrier::Vertical => 0,
+    };
+    (ret, label)
+}
+
+// Triple barrier labels, as in chapter 3 of Advances in Financial Machine
+// Learning. Each event is labeled by which of the profit take, stop loss or
+// vertical barrier the price touches first, checking every tick after the
+// event rather than only bar closes. Events are printed with the time of the
+// touch, the return from entry, the label (1 for profit take, -1 for stop
+// loss, and the sign of the return at the vertical barrier), the barrier and
+// the daily volatility the barriers were scaled by.
+//
+// When meta-labeling, the barriers follow each event's side and the return
+// is the one realized by taking that side, labeled 1 (bet) if it's positive
+// and 0 (no bet) otherwise.
+pub fn triple_barrier(opts: &TripleBarrierOptions) -> Result<(), Box<dyn Error>> {
+    let events = read_events(opts.events_file, opts.sides_file, opts.timezone)?;
+    let mut labeler = Labeler::new(positions(opts, &events)?, events.len());
+
+    let in_dir_path = Path::new("ticks").join(opts.symbol);
+    info!(
+        in_dir_path = in_dir_path.to_str().unwrap(),
+        bars_file = opts.bars_file.to_str().unwrap(),
+        n_events = events.len(),
+        "Labeling events"
+    );
+    'files: for csv_file in list_tick_files(in_dir_path)? {
+        if labeler.is_done() {
+            break;
+        }
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(opts.profile.delimiter())
+            .has_headers(opts.profile.headers())
+            .from_reader(File::open(&csv_file)?);
+        let columns = if opts.profile.headers() {
+            opts.profile.indexes(rdr.headers()?)?
+        } else {
+            opts.profile.indexes(&csv::StringRecord::new())?
+        };
+        let mut tick = csv::ByteRecord::new();
+        while rdr.read_byte_record(&mut tick)? {
+            let timestamp = String::from_utf8_lossy(&tick[columns.timestamp]);
+            let date_time = match opts.profile.parse_timestamp(&timestamp)? {
+                Some(date_time) => date_time,
+                None => continue,
+            };
+            let last = String::from_utf8_lossy(&tick[columns.price]).parse::<f64>()?;
+            labeler.tick(date_time, last);
+            if labeler.is_done() {
+                break 'files;
+            }
+        }
+    }
+    let unfinished = labeler.pending.len() + labeler.active.len();
+    if unfinished > 0 {
+        info!(
+            n_events = unfinished,
+            "Leaving out events whose barriers the ticks don't reach"
+        );
+    }
+
+    let meta = opts.sides_file.is_some();
+    let mut header = String::from("date_time,touch_date_time");
+    if meta {
+        header.push_str(",side");
+    }
+    header.push_str(",return,label,barrier,target");
+    for column in &opts.bar_columns {
+        header.push_str(&format!(",bar_{}", column));
+    }
+    println!("{}", header);
+    for (event, touch) in events.iter().zip(labeler.touches) {
+        if let Some((position, touch)) = touch {
+            let mut line = format!(
+                "{},{}",
+                event.date_time,
+                touch
+                    .date_time
+                    .with_timezone(&opts.timezone)
+                    .format(crate::iqfeed_date_time::FORMAT)
+            );
+            if meta {
+                line.push_str(&format!(",{}", position.side));
+            }
+            let (ret, label) = return_and_label(&position, &touch, meta);
+            line.push_str(&format!(",{},{}", ret, label));
+            line.push_str(&format!(",{},{}", touch.barrier.name(), position.target));
+            for value in &position.bar_columns {
+                line.push(',');
+                line.push_str(value);
+            }
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+pub struct TrendScanningOptions<'o> {
+    pub input_file: &'o str,
+    // range of forward horizons, in bars, that trends are fitted over
+    pub min_horizon: usize,
+    pub max_horizon: usize,
+}
+
+// t-value of the slope of an OLS fit of ys against 0, 1, 2, ... for each
+// number of ys from min_horizon up, keeping the one with the largest
+// magnitude. Prices are taken relative to the first so that the sums stay
+// small.
+fn scan_trend(ys: &VecDeque<f64>, min_horizon: usize) -> Option<(usize, f64)> {
+    let (mut sx, mut sy, mut sxx, mut sxy, mut syy) = (0., 0., 0., 0., 0.);
+    let mut best: Option<(usize, f64)> = None;
+    for (i, y) in ys.iter().enumerate() {
+        let (x, y) = (i as f64, y - ys[0]);
+        sx += x;
+        sy += y;
+        sxx += x * x;
+        sxy += x * y;
+        syy += y * y;
+        let n = (i + 1) as f64;
+        if i + 1 < min_horizon {
+            continue;
+        }
+        let ss_x = sxx - sx * sx / n;
+        let ss_xy = sxy - sx * sy / n;
+        let ss_y = syy - sy * sy / n;
+        let slope = ss_xy / ss_x;
+        let residuals = (ss_y - slope * ss_xy).max(0.);
+        let t_value = slope / (residuals / (n - 2.) / ss_x).sqrt();
+        if t_value.is_nan() {
+            continue;
+        }
+        if best.is_none_or(|(_, best)| t_value.abs() > best.abs()) {
+            best = Some((i + 1, t_value));
+        }
+    }
+    best
+}
+
+// Trend scanning labels, as in Machine Learning for Asset Managers. For each
+// bar, a linear trend is fitted to the closes of it and the bars after it over
+// every horizon from min_horizon to max_horizon bars, and the bar is labeled
+// with the sign of the t-value of the strongest trend. Bars are printed with
+// the date_time of the last bar of that trend, its horizon, t-value and
+// label. The last bars, without max_horizon bars ahead of them, aren't
+// labeled.
+pub fn trend_scanning(opts: &TrendScanningOptions) -> Result<(), Box<dyn Error>> {
+    if opts.min_horizon < 3 || opts.max_horizon < opts.min_horizon {
+        return Err("Horizons need at least 3 bars, up to the max horizon".into());
+    }
+    let mut rdr = csv::Reader::from_path(opts.input_file)?;
+    // date_times as written, so that labels line up with bars
+    let mut date_times = VecDeque::with_capacity(opts.max_horizon);
+    let mut closes = VecDeque::with_capacity(opts.max_horizon);
+    println!("date_time,end_date_time,horizon,t_value,label");
+
+    for result in rdr.deserialize() {
+        let bar: crate::Bar = result?;
+        date_times.push_back(bar.date_time);
+        closes.push_back(bar.close);
+        if closes.len() < opts.max_horizon {
+            continue;
+        }
+        if let Some((horizon, t_value)) = scan_trend(&closes, opts.min_horizon) {
+            let label = if t_value > 0. {
+                1
+            } else if t_value < 0. {
+                -1
+            } else {
+                0
+            };
+            println!(
+                "{},{},{},{},{}",
+                date_times[0],
+                date_times[horizon - 1],
+                horizon,
+                t_value,
+                label
+            );
+        }
+        date_times.pop_front();
+        closes.pop_front();
+    }
+
+    Ok(())
+}
+
+pub struct WeightsOptions<'o> {
+    pub input_file: &'o str,
+    pub labels_file: &'o str,
+    // what the timestamps of bars and labels are local to
+    pub timezone: Tz,
+    // weight left on the oldest label by time decay, 1 for no decay and
+    // negative to give the oldest fraction of labels no weight at all
+    pub decay: f64,
+    // where to write the number of concurrent labels at each bar
+    pub concurrency_file: Option<&'o str>,
+}
+
+// Running sums over bars, of 1 / concurrency and return / concurrency, so
+// that the sums over any label's bars are the difference between the sums at
+// its end and start.
+#[derive(Debug, Clone, Copy, Default)]
+struct Attribution {
+    n_bars: usize,
+    uniqueness: f64,
+    returns: f64,
+}
+
+struct Label {
+    date_time: String,
+    end_date_time: String,
+    start: DateTime<Tz>,
+    end: DateTime<Tz>,
+    sums_at_start: Attribution,
+    sums_at_end: Attribution,
+}
+
+// Labels with a date_time and either a touch_date_time (from triple-barrier)
+// or an end_date_time (from trend-scanning), in order, and whether their ends
+// are inclusive. A touch happens during the bar that opened before it, while
+// the end of a trend is the date_time of its last bar.
+fn read_labels(labels_file: &str, timezone: Tz) -> Result<(Vec<Label>, bool), Box<dyn Error>> {
+    let mut rdr = csv::Reader::from_path(labels_file)?;
+    let headers = rdr.headers()?.clone();
+    let date_time_index = column_index(&headers, "date_time")?;
+    let (end_index, inclusive_end) = match column_index(&headers, "touch_date_time") {
+        Ok(index) => (index, false),
+        Err(_) => (column_index(&headers, "end_date_time")?, true),
+    };
+    let mut labels = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        labels.push(Label {
+            date_time: String::from(&record[date_time_index]),
+            end_date_time: String::from(&record[end_index]),
+            start: parse_local(&record[date_time_index], timezone)?,
+            end: parse_local(&record[end_index], timezone)?,
+            sums_at_start: Attribution::default(),
+            sums_at_end: Attribution::default(),
+        });
+    }
+    labels.sort_by_key(|label| label.start);
+    Ok((labels, inclusive_end))
+}
+
+// Linear time decay of sample weights over cumulative uniqueness, from
+// chapter 4 of Advances in Financial Machine Learning. The newest label keeps
+// all of its weight and the oldest keeps decay of it.
+fn time_decay(uniqueness: &[f64], decay: f64) -> Vec<f64> {
+    let total = uniqueness.iter().sum::<f64>();
+    let slope = if decay >= 0. {
+        (1. - decay) / total
+    } else {
+        1. / ((decay + 1.) * total)
+    };
+    let intercept = 1. - slope * total;
+    let mut cumulative = 0.;
+    uniqueness
+        .iter()
+        .map(|u| {
+            cumulative += u;
+            (intercept + slope * cumulative).max(0.)
+        })
+        .collect()
+}
+
+struct Weight {
+    avg_uniqueness: f64,
+    return_weight: f64,
+    decay: f64,
+}
+
+// Sample weights for labels that overlap, as in chapter 4 of Advances in
+// Financial Machine Learning. A label covers the bars that open after its
+// date_time and before its touch or up to the end of its trend, and each bar
+// is shared between the labels that cover it. Each label is weighted by its
+// average uniqueness (the mean of 1 / the number of labels covering each of
+// its bars), the absolute sum of its bars' log returns shared the same way,
+// normalized to average 1, and the time decay. Labels that don't cover any
+// bars, e.g. ones touched within the bar they started in, are left out, since
+// there's nothing to weight them by.
+//
+// Bars are streamed, keeping only the labels in memory.
+fn sample_weights(opts: &WeightsOptions) -> Result<Vec<(Label, Weight)>, Box<dyn Error>> {
+    if opts.decay > 1. || opts.decay <= -1. {
+        return Err("Decay must be greater than -1 and at most 1".into());
+    }
+    let (mut labels, inclusive_end) = read_labels(opts.labels_file, opts.timezone)?;
+    let ended = |end: DateTime<Tz>, date_time: DateTime<Tz>| {
+        if inclusive_end {
+            end < date_time
+        } else {
+            end <= date_time
+        }
+    };
+    let mut concurrency_out = match opts.concurrency_file {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(path)?);
+            writeln!(out, "date_time,concurrency")?;
+            Some(out)
+        }
+        None => None,
+    };
+    let mut rdr = csv::Reader::from_path(opts.input_file)?;
+    let headers = rdr.headers()?.clone();
+    let date_time_index = column_index(&headers, "date_time")?;
+    let close_index = column_index(&headers, "close")?;
+    // labels covering the current bar, by when they end
+    let mut active: BinaryHeap<Reverse<(DateTime<Tz>, usize)>> = BinaryHeap::new();
+    let mut next_label = 0;
+    let mut sums = Attribution::default();
+    let mut prev_close = None;
+
+    for record in rdr.records() {
+        let record = record?;
+        let date_time = parse_local(&record[date_time_index], opts.timezone)?;
+        let close = record[close_index].parse::<f64>()?;
+        while let Some(Reverse((end, i))) = active.peek().cloned() {
+            if !ended(end, date_time) {
+                break;
+            }
+            active.pop();
+            labels[i].sums_at_end = sums;
+        }
+        while next_label < labels.len() && labels[next_label].start < date_time {
+            if !ended(labels[next_label].end, date_time) {
+                labels[next_label].sums_at_start = sums;
+                active.push(Reverse((labels[next_label].end, next_label)));
+            }
+            next_label += 1;
+        }
+        let concurrency = active.len();
+        if concurrency > 0 {
+            let ret = prev_close.map_or(0., |prev_close: f64| (close / prev_close).ln());
+            sums.n_bars += 1;
+            sums.uniqueness += 1. / concurrency as f64;
+            sums.returns += ret / concurrency as f64;
+        }
+        if let Some(out) = concurrency_out.as_mut() {
+            writeln!(out, "{},{}", &record[date_time_index], concurrency)?;
+        }
+        prev_close = Some(close);
+    }
+    // labels that end after the last bar only cover the bars so far
+    for Reverse((_, i)) in active {
+        labels[i].sums_at_end = sums;
+    }
+    if let Some(mut out) = concurrency_out {
+        out.flush()?;
+    }
+
+    let n_read = labels.len();
+    labels.retain(|label| label.sums_at_end.n_bars > label.sums_at_start.n_bars);
+    if labels.len() < n_read {
+        info!(
+            n_labels = n_read - labels.len(),
+            "Leaving out labels that don't cover any bars"
+        );
+    }
+    let uniqueness = labels
+        .iter()
+        .map(|label| {
+            let n_bars = label.sums_at_end.n_bars - label.sums_at_start.n_bars;
+            (label.sums_at_end.uniqueness - label.sums_at_start.uniqueness) / n_bars as f64
+        })
+        .collect::<Vec<f64>>();
+    let returns = labels
+        .iter()
+        .map(|label| (label.sums_at_end.returns - label.sums_at_start.returns).abs())
+        .collect::<Vec<f64>>();
+    let total_returns = returns.iter().sum::<f64>();
+    let decay = time_decay(&uniqueness, opts.decay);
+    let n_labels = labels.len();
+    let weights = (0..n_labels).map(|i| Weight {
+        avg_uniqueness: uniqueness[i],
+        // if prices never moved, no label earned more than any other
+        return_weight: if total_returns > 0. {
+            returns[i] * n_labels as f64 / total_returns
+        } else {
+            1.
+        },
+        decay: decay[i],
+    });
+    Ok(labels.into_iter().zip(weights).collect())
+}
+
+// Prints each label with its sample weights, see sample_weights, and their
+// product as the weight.
+pub fn weights(opts: &WeightsOptions) -> Result<(), Box<dyn Error>> {
+    let weights = sample_weights(opts)?;
+    println!("date_time,end_date_time,avg_uniqueness,return_weight,decay,weight");
+    for (label, weight) in weights {
+        println!(
+            "{},{},{},{},{},{}",
+            label.date_time,
+            label.end_date_time,
+            weight.avg_uniqueness,
+            weight.return_weight,
+            weight.decay,
+            weight.return_weight * weight.decay
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+
+    fn new_york(s: &str) -> DateTime<Tz> {
+        parse_local(&format!("{}.000000", s), New_York).unwrap()
+    }
+
+    // Writes content to a file of its own in the temp directory.
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("feat-{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn triple_barrier_options(bars_file: &Path) -> TripleBarrierOptions<'static> {
+        TripleBarrierOptions {
+            symbol: "TST",
+            bars_file: bars_file.to_path_buf(),
+            events_file: "",
+            profile: Profile::iqfeed(),
+            timezone: New_York,
+            profit_take: 1.,
+            stop_loss: 1.,
+            vertical_barrier: Duration::hours(1),
+            vol_span: 1,
+            sides_file: None,
+            bar_columns: Vec::new(),
+        }
+    }
+
+    fn position(event: usize, entry: &str, entry_price: f64, end: &str) -> Position {
+        Position {
+            event,
+            entry_time: new_york(entry),
+            entry_price,
+            side: 1.,
+            upper: Some(entry_price * 1.02),
+            lower: Some(entry_price * 0.99),
+            end: new_york(end),
+            target: 0.01,
+            last: entry_price,
+            bar_columns: Vec::new(),
+        }
+    }
+
+    // The barrier each event touched, and when and at what price.
+    fn label(
+        positions: Vec<Position>,
+        ticks: &[(&str, f64)],
+    ) -> Vec<Option<(&'static str, DateTime<Tz>, f64)>> {
+        let n_events = positions.len();
+        let mut labeler = Labeler::new(positions.into_iter().collect(), n_events);
+        for (date_time, last) in ticks {
+            labeler.tick(new_york(date_time), *last);
+        }
+        labeler
+            .touches
+            .into_iter()
+            .map(|touch| {
+                touch.map(|(_, touch)| (touch.barrier.name(), touch.date_time, touch.price))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn barriers_touched_first() {
+        let touches = label(
+            vec![
+                position(0, "2021-09-15 10:00:00", 100., "2021-09-15 12:00:00"),
+                position(1, "2021-09-15 10:30:00", 101., "2021-09-15 12:30:00"),
+                position(2, "2021-09-15 11:00:00", 101., "2021-09-15 11:30:00"),
+            ],
+            &[
+                ("2021-09-15 10:00:00", 100.),
+                ("2021-09-15 10:15:00", 101.),
+                // the stop loss of the second position
+                ("2021-09-15 10:45:00", 99.9),
+                ("2021-09-15 11:05:00", 101.),
+                // the profit take of the first position
+                ("2021-09-15 11:10:00", 102.),
+                // past the vertical barrier of the third position
+                ("2021-09-15 11:40:00", 100.5),
+            ],
+        );
+        assert_eq!(
+            touches,
+            vec![
+                Some(("profit_take", new_york("2021-09-15 11:10:00"), 102.)),
+                Some(("stop_loss", new_york("2021-09-15 10:45:00"), 99.9)),
+                // exits at the last price before the vertical barrier
+                Some(("vertical", new_york("2021-09-15 11:30:00"), 102.)),
+            ]
+        );
+    }
+
+    #[test]
+    fn vertical_barrier_counts_from_entry() {
+        let bars_file = temp_file(
+            "vertical-barrier-bars",
+            "date_time,close\n\
+             2021-09-13 10:00:00.000000,100\n\
+             2021-09-14 10:01:00.000000,101\n\
+             2021-09-15 10:02:00.000000,99\n\
+             2021-09-15 10:30:00.000000,100\n\
+             2021-09-15 16:00:00.000000,101\n",
+        );
+        let opts = triple_barrier_options(&bars_file);
+        // the example in the README, an event that falls in a bar that's
+        // longer than the vertical barrier
+        let events = vec![Event {
+            date_time: String::from("2021-09-15 10:40:00.000000"),
+            parsed: new_york("2021-09-15 10:40:00"),
+            side: None,
+        }];
+        let positions = positions(&opts, &events);
+        std::fs::remove_file(&bars_file).unwrap();
+        let positions = positions.unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].entry_time, new_york("2021-09-15 16:00:00"));
+        assert_eq!(positions[0].entry_price, 100.);
+        assert_eq!(positions[0].end, new_york("2021-09-15 17:00:00"));
+
+        let touches = label(
+            positions.into_iter().collect(),
+            &[
+                ("2021-09-15 16:00:00", 100.),
+                ("2021-09-15 16:30:00", 100.2),
+                ("2021-09-15 17:30:00", 100.4),
+            ],
+        );
+        assert_eq!(
+            touches,
+            vec![Some(("vertical", new_york("2021-09-15 17:00:00"), 100.2))]
+        );
+    }
+
+    #[test]
+    fn events_without_an_entry_are_left_out() {
+        let bars_file = temp_file(
+            "left-out-bars",
+            "date_time,close\n\
+             2021-09-13 10:00:00.000000,100\n\
+             2021-09-14 10:01:00.000000,101\n\
+             2021-09-14 16:00:00.000000,100\n",
+        );
+        let opts = triple_barrier_options(&bars_file);
+        let events = [
+            // before the first bar
+            "2021-09-13 09:00:00",
+            // before there's a day of bars for the daily volatility
+            "2021-09-13 12:00:00",
+            "2021-09-14 12:00:00",
+            // without a bar after it
+            "2021-09-14 16:30:00",
+        ]
+        .iter()
+        .map(|date_time| Event {
+            date_time: format!("{}.000000", date_time),
+            parsed: new_york(date_time),
+            side: None,
+        })
+        .collect::<Vec<_>>();
+        let positions = positions(&opts, &events);
+        std::fs::remove_file(&bars_file).unwrap();
+        let positions = positions.unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].event, 2);
+        assert_eq!(positions[0].entry_time, new_york("2021-09-14 16:00:00"));
+    }
+
+    #[test]
+    fn events_take_the_last_side_before_them() {
+        let events_file = temp_file(
+            "sided-events",
+            "date_time\n\
+             2021-09-15 10:05:00.000000\n\
+             2021-09-15 10:00:00.000000\n\
+             2021-09-15 10:10:00.000000\n\
+             2021-09-15 10:15:00.000000\n\
+             2021-09-15 10:20:00.000000\n\
+             2021-09-15 10:25:00.000000\n",
+        );
+        let sides_file = temp_file(
+            "sides",
+            "date_time,side\n\
+             2021-09-15 10:10:00.000000,-2\n\
+             2021-09-15 10:02:00.000000,1\n\
+             2021-09-15 10:12:00.000000,0\n\
+             2021-09-15 10:18:00.000000,0.5\n",
+        );
+        let events = read_events(
+            events_file.to_str().unwrap(),
+            Some(sides_file.to_str().unwrap()),
+            New_York,
+        );
+        std::fs::remove_file(&events_file).unwrap();
+        std::fs::remove_file(&sides_file).unwrap();
+        let events = events
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.date_time, event.side))
+            .collect::<Vec<_>>();
+        // 10:00 is before any side and 10:15 after a side of 0, the others
+        // carry the last side at or before them
+        assert_eq!(
+            events,
+            vec![
+                (String::from("2021-09-15 10:05:00.000000"), Some(1.)),
+                (String::from("2021-09-15 10:10:00.000000"), Some(-1.)),
+                (String::from("2021-09-15 10:20:00.000000"), Some(1.)),
+                (String::from("2021-09-15 10:25:00.000000"), Some(1.)),
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_labels() {
+        let labels = |side: f64, price: f64, barrier: Barrier, meta: bool| {
+            let position = Position {
+                side,
+                ..position(0, "2021-09-15 10:00:00", 100., "2021-09-15 12:00:00")
+            };
+            let touch = Touch {
+                date_time: new_york("2021-09-15 11:00:00"),
+                price,
+                barrier,
+            };
+            return_and_label(&position, &touch, meta)
+        };
+        assert_eq!(labels(1., 125., Barrier::ProfitTake, false), (0.25, 1));
+        assert_eq!(labels(1., 75., Barrier::StopLoss, false), (-0.25, -1));
+        assert_eq!(labels(1., 100., Barrier::Vertical, false), (0., 0));
+        // the return of taking the side, and whether to bet on it
+        assert_eq!(labels(1., 125., Barrier::ProfitTake, true), (0.25, 1));
+        assert_eq!(labels(1., 75., Barrier::StopLoss, true), (-0.25, 0));
+        assert_eq!(labels(-1., 75., Barrier::ProfitTake, true), (0.25, 1));
+        assert_eq!(labels(-1., 125., Barrier::StopLoss, true), (-0.25, 0));
+        assert_eq!(labels(-1., 100., Barrier::Vertical, true), (0., 0));
+    }
+
+    // Weights for three labels over bars at 10:00 to 10:05 with closes.
+    fn weights(name: &str, closes: &[f64], decay: f64) -> Vec<(String, f64, f64, f64)> {
+        let mut bars = String::from("date_time,close\n");
+        for (i, close) in closes.iter().enumerate() {
+            bars.push_str(&format!("2021-09-15 10:0{}:00.000000,{}\n", i, close));
+        }
+        let bars_file = temp_file(&format!("{}-bars", name), &bars);
+        // the first two overlap on the 10:02 bar, while the last is touched
+        // before the next bar opens
+        let labels_file = temp_file(
+            &format!("{}-labels", name),
+            "date_time,touch_date_time\n\
+             2021-09-15 09:59:30.000000,2021-09-15 10:02:30.000000\n\
+             2021-09-15 10:01:30.000000,2021-09-15 10:05:30.000000\n\
+             2021-09-15 10:04:10.000000,2021-09-15 10:04:20.000000\n",
+        );
+        let weights = sample_weights(&WeightsOptions {
+            input_file: bars_file.to_str().unwrap(),
+            labels_file: labels_file.to_str().unwrap(),
+            timezone: New_York,
+            decay,
+            concurrency_file: None,
+        });
+        std::fs::remove_file(&bars_file).unwrap();
+        std::fs::remove_file(&labels_file).unwrap();
+        weights
+            .unwrap()
+            .into_iter()
+            .map(|(label, weight)| {
+                (
+                    label.date_time,
+                    weight.avg_uniqueness,
+                    weight.return_weight,
+                    weight.decay,
+                )
+            })
+            .collect()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-12, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn weights_share_bars_between_labels() {
+        let closes = [100., 101., 102., 101., 103., 104.];
+        let weights = weights("shared", &closes, 0.);
+        assert_eq!(weights.len(), 2);
+        assert_eq!(weights[0].0, "2021-09-15 09:59:30.000000");
+        assert_eq!(weights[1].0, "2021-09-15 10:01:30.000000");
+        // concurrency is 1, 1, 2, 1, 1, 1 from 10:00 to 10:05
+        let (uniqueness_a, uniqueness_b) = ((1. + 1. + 0.5) / 3., (0.5 + 1. + 1. + 1.) / 4.);
+        assert_close(weights[0].1, uniqueness_a);
+        assert_close(weights[1].1, uniqueness_b);
+        // the first bar has no return, since there's no close before it
+        let ret = |i: usize| (closes[i] / closes[i - 1]).ln();
+        let returns_a = (ret(1) + ret(2) / 2.).abs();
+        let returns_b = (ret(2) / 2. + ret(3) + ret(4) + ret(5)).abs();
+        let total = returns_a + returns_b;
+        assert_close(weights[0].2, 2. * returns_a / total);
+        assert_close(weights[1].2, 2. * returns_b / total);
+        // with a decay of 0, weights grow from 0 with cumulative uniqueness
+        assert_close(weights[0].3, uniqueness_a / (uniqueness_a + uniqueness_b));
+        assert_close(weights[1].3, 1.);
+    }
+
+    #[test]
+    fn weights_without_returns() {
+        let weights = weights("flat", &[100.; 6], 1.);
+        assert_eq!(weights.len(), 2);
+        for (_, _, return_weight, decay) in weights {
+            assert_eq!(return_weight, 1.);
+            assert_eq!(decay, 1.);
+        }
+    }
+
+    #[test]
+    fn strongest_trend() {
+        // a rising trend over the first 6 bars, which then falls back
+        let mut ys = (0..6)
+            .map(|i| i as f64 + if i % 2 == 0 { 0.1 } else { -0.1 })
+            .collect::<VecDeque<f64>>();
+        ys.extend(&[1., 0.5]);
+        let (horizon, t_value) = scan_trend(&ys, 3).unwrap();
+        assert_eq!(horizon, 6);
+        assert!(t_value > 0.);
+        let falling = ys.iter().map(|y| -y).collect::<VecDeque<f64>>();
+        assert_eq!(scan_trend(&falling, 3), Some((6, -t_value)));
+    }
+
+    #[test]
+    fn flat_closes_have_no_trend() {
+        let ys = vec![100.; 8].into_iter().collect::<VecDeque<f64>>();
+        assert_eq!(scan_trend(&ys, 3), None);
+    }
+}
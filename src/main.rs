@@ -1,6 +1,15 @@
 mod bars;
+mod calendar;
+mod classify;
+mod events;
 mod iqfeed_date_time;
+mod labels;
+mod profile;
+mod session;
+mod settings;
+mod stats;
 mod ticks;
+mod timestamp;
 
 use chrono::{DateTime, Duration};
 use chrono_tz::Tz;
@@ -21,15 +30,13 @@ use std::time::SystemTime;
 use tracing::Level;
 use tracing::{self, debug, error, info};
 
+// The columns of bar files that are read back in, such as by vol and
+// trend-scanning labels.
 #[derive(Debug, Deserialize)]
 struct Bar {
-    #[serde(with = "iqfeed_date_time")]
-    date_time: DateTime<Tz>,
-    open: f64,
-    high: f64,
-    low: f64,
+    // in whatever timezone the bars were written in
+    date_time: String,
     close: f64,
-    volume: f64,
 }
 #[derive(Debug)]
 struct ProcessingError {
@@ -49,7 +56,7 @@ fn nan_or_val(x: Option<f64>) -> String {
     }
 }
 
-fn daily_vol(csv_path: &str) -> Result<(), Box<dyn Error>> {
+fn daily_vol(csv_path: &str, timezone: Tz) -> Result<(), Box<dyn Error>> {
     let file = File::open(csv_path)?;
     let lookback = 20;
     let lookback_f64 = lookback as f64;
@@ -59,7 +66,7 @@ fn daily_vol(csv_path: &str) -> Result<(), Box<dyn Error>> {
     let mut rdr = csv::Reader::from_reader(file);
     let mut bars = rdr.deserialize();
     let first_bar: Bar = bars.next().unwrap()?;
-    let mut day_cur: DateTime<Tz> = first_bar.date_time;
+    let mut day_cur: DateTime<Tz> = timestamp::parse_local(&first_bar.date_time, timezone)?;
     let mut price_cur = first_bar.close;
     let mut sma_sum = 0.;
     let mut ewma_daily_vols: Vec<Option<f64>> = Vec::new();
@@ -67,7 +74,8 @@ fn daily_vol(csv_path: &str) -> Result<(), Box<dyn Error>> {
 
     for result in bars {
         let bar: Bar = result?;
-        if bar.date_time.signed_duration_since(day_cur) > Duration::days(1) {
+        let date_time = timestamp::parse_local(&bar.date_time, timezone)?;
+        if date_time.signed_duration_since(day_cur) > Duration::days(1) {
             n_days += 1;
             let ret = (bar.close / price_cur) - 1.;
             if n_days > lookback {
@@ -86,8 +94,8 @@ fn daily_vol(csv_path: &str) -> Result<(), Box<dyn Error>> {
             } else {
                 sma_sum += ret;
             }
-            println!("{},{},{},{}", day_cur, bar.date_time, ret, nan_or_val(ewma),);
-            day_cur = bar.date_time;
+            println!("{},{},{},{}", day_cur, date_time, ret, nan_or_val(ewma),);
+            day_cur = date_time;
             price_cur = bar.close;
         }
     }
@@ -96,13 +104,10 @@ fn daily_vol(csv_path: &str) -> Result<(), Box<dyn Error>> {
 }
 
 fn symbol_lookup(query: &str) -> Result<(), Box<dyn Error>> {
-    let mut stream = match TcpStream::connect("127.0.0.1:9100") {
-        Ok(s) => s,
-        Err(e) => return Err(e.into()),
-    };
+    let mut stream = TcpStream::connect("127.0.0.1:9100")?;
     let filter_type = "";
     let filter_value = "";
-    match stream.write(
+    match stream.write_all(
         format!(
             // http://www.iqfeed.net/dev/api/docs/SymbolLookupviaTCPIP.cfm
             "S,SET PROTOCOL,6.2\r
@@ -141,7 +146,9 @@ fn check_iqfeed_health() -> i32 {
                 return;
             }
         };
-        match stream.write("S,SET PROTOCOL,5.1\r\nHTT,NONSENSE_SYMBOL,,,,,,1,\r\n\r\n".as_bytes()) {
+        match stream
+            .write_all("S,SET PROTOCOL,6.2\r\nHTT,NONSENSE_SYMBOL,,,,,,1,\r\n\r\n".as_bytes())
+        {
             Ok(_) => {}
             Err(e) => {
                 sender.send(Err(e)).unwrap();
@@ -151,12 +158,12 @@ fn check_iqfeed_health() -> i32 {
         let mut lines = BufReader::new(stream).lines();
 
         // should get back a response
-        // S,CURRENT PROTOCOL,5.1
+        // S,CURRENT PROTOCOL,6.2
         if let Some(line) = lines.next() {
             match line {
                 Ok(line) => {
                     info!("Got a response: {:?}", line);
-                    if line != "S,CURRENT PROTOCOL,5.1" {
+                    if line != "S,CURRENT PROTOCOL,6.2" {
                         sender
                             .send(Err(std::io::Error::new(
                                 ErrorKind::InvalidData,
@@ -181,7 +188,7 @@ fn check_iqfeed_health() -> i32 {
             "timeout trying to connect to IQFeed",
         ))) {}
     });
-    return match receiver.recv() {
+    match receiver.recv() {
         Ok(msg) => match msg {
             Ok(_) => 0,
             Err(e) => {
@@ -193,7 +200,7 @@ fn check_iqfeed_health() -> i32 {
             error!("{:?}", e);
             2
         }
-    };
+    }
 }
 
 fn main() {
@@ -221,6 +228,11 @@ fn main() {
                     Arg::new("no_mkt_hours")
                         .long("no_mkt_hours")
                         .takes_value(false),
+                )
+                .arg(
+                    Arg::new("calendars")
+                        .long("calendars")
+                        .default_value("calendars.toml"),
                 ),
         )
         .subcommand(
@@ -231,37 +243,321 @@ fn main() {
         .subcommand(
             App::new("bars")
                 .about("Gets bars from ticks")
-                .arg(Arg::new("multiply").long("multiply").default_value("1."))
-                .arg(Arg::new("delimiter").long("delimiter").default_value(","))
+                .arg(Arg::new("multiply").long("multiply").takes_value(true))
+                .arg(Arg::new("profile").long("profile").takes_value(true))
+                .arg(Arg::new("delimiter").long("delimiter").takes_value(true))
                 .arg(
                     Arg::new("timestamp_index")
                         .long("timestamp_index")
-                        .default_value("1"),
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("timestamp_column")
+                        .long("timestamp_column")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("last_index").long("last_index").takes_value(true))
+                .arg(
+                    Arg::new("price_column")
+                        .long("price_column")
+                        .takes_value(true),
                 )
-                .arg(Arg::new("last_index").long("last_index").default_value("2"))
                 .arg(
                     Arg::new("volume_index")
                         .long("volume_index")
-                        .default_value("3"),
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("volume_column")
+                        .long("volume_column")
+                        .visible_alias("size_column")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("bid_index").long("bid_index").takes_value(true))
+                .arg(Arg::new("bid_column").long("bid_column").takes_value(true))
+                .arg(Arg::new("ask_index").long("ask_index").takes_value(true))
+                .arg(Arg::new("ask_column").long("ask_column").takes_value(true))
+                .arg(
+                    Arg::new("aggressor_index")
+                        .long("aggressor_index")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("aggressor_column")
+                        .long("aggressor_column")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("classification")
+                        .long("classification")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("bvc_span").long("bvc_span").default_value("20"))
+                .arg(Arg::new("columns").long("columns").takes_value(true))
+                .arg(
+                    Arg::new("incremental")
+                        .long("incremental")
+                        .takes_value(false),
+                )
+                .arg(Arg::new("output").long("output").takes_value(true))
+                .arg(Arg::new("keep").long("keep").takes_value(true))
+                .arg(Arg::new("max_age").long("max_age").takes_value(true))
+                .arg(
+                    Arg::new("dollar_threshold")
+                        .long("dollar_threshold")
+                        .visible_alias("threshold")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("tick_size").long("tick_size").takes_value(true))
+                .arg(
+                    Arg::new("range_ticks")
+                        .long("range_ticks")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("brick_size").long("brick_size").takes_value(true))
+                .arg(
+                    Arg::new("bars_per_day")
+                        .long("bars_per_day")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("threshold_lookback_days")
+                        .long("threshold_lookback_days")
+                        .default_value("20"),
+                )
+                .arg(
+                    Arg::new("tick_threshold")
+                        .long("tick_threshold")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("volume_threshold")
+                        .long("volume_threshold")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("expected_imbalance_span")
+                        .long("expected_imbalance_span")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("expected_imbalance_per_bar")
+                        .long("expected_imbalance_per_bar")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("expected_ticks_span")
+                        .long("expected_ticks_span")
+                        .default_value("20"),
+                )
+                .arg(
+                    Arg::new("warmup_bars")
+                        .long("warmup_bars")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("min_expected_ticks")
+                        .long("min_expected_ticks")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("max_expected_ticks")
+                        .long("max_expected_ticks")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("interval").long("interval").default_value("15m"))
+                .arg(
+                    Arg::new("timezone")
+                        .long("timezone")
+                        .default_value("America/New_York"),
+                )
+                .arg(Arg::new("session").long("session").takes_value(true))
+                .arg(
+                    Arg::new("drop_out_of_session")
+                        .long("drop_out_of_session")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::new("settings")
+                        .long("settings")
+                        .default_value("bars.toml"),
+                )
+                .arg(
+                    Arg::new("calendars")
+                        .long("calendars")
+                        .default_value("calendars.toml"),
                 )
                 .arg(
                     Arg::new("timestamp_type")
                         .long("timestamp_type")
-                        .default_value("string"),
+                        .takes_value(true),
                 )
+                .arg(
+                    Arg::new("input_timezone")
+                        .long("input_timezone")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("dst_policy").long("dst_policy").takes_value(true))
                 .arg(Arg::new("bar_type").required(true))
                 .arg(Arg::new("symbol").required(true)),
         )
         .subcommand(
             App::new("vol")
                 .about("Gets daily volatility from bars")
-                .arg(Arg::new("input_file").required(true)),
+                .arg(Arg::new("input_file").required(true))
+                .arg(
+                    Arg::new("timezone")
+                        .long("timezone")
+                        .default_value("America/New_York"),
+                ),
+        )
+        .subcommand(
+            App::new("cusum")
+                .about("Samples events from bars or ticks with a CUSUM filter")
+                .arg(Arg::new("input_file").required(true))
+                .arg(
+                    Arg::new("timestamp_column")
+                        .long("timestamp_column")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("price_column")
+                        .long("price_column")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("threshold")
+                        .long("threshold")
+                        .default_value("0.01"),
+                )
+                .arg(
+                    Arg::new("vol_multiple")
+                        .long("vol_multiple")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("vol_span").long("vol_span").default_value("20"))
+                .arg(Arg::new("profile").long("profile").takes_value(true))
+                .arg(
+                    Arg::new("settings")
+                        .long("settings")
+                        .default_value("bars.toml"),
+                )
+                .arg(
+                    Arg::new("timestamp_type")
+                        .long("timestamp_type")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("input_timezone")
+                        .long("input_timezone")
+                        .takes_value(true),
+                )
+                .arg(Arg::new("dst_policy").long("dst_policy").takes_value(true))
+                .arg(
+                    Arg::new("timezone")
+                        .long("timezone")
+                        .default_value("America/New_York"),
+                ),
+        )
+        .subcommand(
+            App::new("label")
+                .about("Labels events for training")
+                .subcommand(
+                    App::new("triple-barrier")
+                        .about("Labels events by the first of three barriers the ticks touch")
+                        .arg(Arg::new("symbol").required(true))
+                        .arg(
+                            Arg::new("events")
+                                .long("events")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(Arg::new("bars").long("bars").takes_value(true))
+                        .arg(Arg::new("sides").long("sides").takes_value(true))
+                        .arg(
+                            Arg::new("bar_columns")
+                                .long("bar_columns")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::new("bar_type")
+                                .long("bar_type")
+                                .default_value("dollar"),
+                        )
+                        .arg(
+                            Arg::new("profit_take")
+                                .long("profit_take")
+                                .default_value("1"),
+                        )
+                        .arg(Arg::new("stop_loss").long("stop_loss").default_value("1"))
+                        .arg(
+                            Arg::new("vertical_barrier")
+                                .long("vertical_barrier")
+                                .default_value("1d"),
+                        )
+                        .arg(Arg::new("vol_span").long("vol_span").default_value("20"))
+                        .arg(
+                            Arg::new("timezone")
+                                .long("timezone")
+                                .default_value("America/New_York"),
+                        )
+                        .arg(Arg::new("profile").long("profile").takes_value(true))
+                        .arg(
+                            Arg::new("settings")
+                                .long("settings")
+                                .default_value("bars.toml"),
+                        ),
+                )
+                .subcommand(
+                    App::new("trend-scanning")
+                        .about("Labels bars by the sign of the strongest trend after them")
+                        .arg(Arg::new("input_file").required(true))
+                        .arg(
+                            Arg::new("min_horizon")
+                                .long("min_horizon")
+                                .default_value("5"),
+                        )
+                        .arg(
+                            Arg::new("max_horizon")
+                                .long("max_horizon")
+                                .default_value("20"),
+                        ),
+                )
+                .subcommand(
+                    App::new("weights")
+                        .about("Weights overlapping labels by their uniqueness and returns")
+                        .arg(Arg::new("input_file").required(true))
+                        .arg(
+                            Arg::new("labels")
+                                .long("labels")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("decay")
+                                .long("decay")
+                                .allow_hyphen_values(true)
+                                .default_value("1"),
+                        )
+                        .arg(
+                            Arg::new("concurrency")
+                                .long("concurrency")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::new("timezone")
+                                .long("timezone")
+                                .default_value("America/New_York"),
+                        ),
+                ),
         )
         .subcommand(App::new("check").about("Check iqfeed health"));
     let matches = app.get_matches_mut();
     let debug = matches.is_present("debug");
 
-    let mut subscriber = tracing_subscriber::fmt().with_ansi(env::consts::OS != "windows"); // term lib has issues w/ Windows
+    let mut subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr) // keep stdout for output, e.g. from vol
+        .with_ansi(env::consts::OS != "windows"); // term lib has issues w/ Windows
     if debug {
         subscriber = subscriber.with_max_level(Level::DEBUG);
     }
@@ -274,52 +570,228 @@ fn main() {
             let subcmd_matches = matches.subcommand_matches("bars").unwrap();
             let bar_type = subcmd_matches.value_of("bar_type");
             let symbol = subcmd_matches.value_of("symbol").unwrap();
-            let multiply = match subcmd_matches.value_of("multiply") {
+            // thresholds and multiply can also be set per symbol, so they're
+            // only resolved once the symbol is known
+            let multiply = subcmd_matches
+                .value_of("multiply")
+                .map(|x| x.parse::<f64>().unwrap());
+            let dollar_threshold = subcmd_matches
+                .value_of("dollar_threshold")
+                .map(|x| x.parse::<f64>().unwrap());
+            let tick_threshold = subcmd_matches
+                .value_of("tick_threshold")
+                .map(|x| x.parse::<u64>().unwrap());
+            let volume_threshold = subcmd_matches
+                .value_of("volume_threshold")
+                .map(|x| x.parse::<f64>().unwrap());
+            let tick_size = subcmd_matches
+                .value_of("tick_size")
+                .map(|x| x.parse::<f64>().unwrap());
+            let range_ticks = subcmd_matches
+                .value_of("range_ticks")
+                .map(|x| x.parse::<u64>().unwrap());
+            let brick_size = subcmd_matches
+                .value_of("brick_size")
+                .map(|x| x.parse::<f64>().unwrap());
+            // columns can be given by index or by name, and override the
+            // input profile
+            let column = |index: &str, name: &str| {
+                subcmd_matches
+                    .value_of(index)
+                    .map(|x| profile::ColumnRef::Index(x.parse::<usize>().unwrap()))
+                    .or_else(|| {
+                        subcmd_matches
+                            .value_of(name)
+                            .map(|x| x.parse::<profile::ColumnRef>().unwrap())
+                    })
+            };
+            let input_columns = profile::Profile {
+                delimiter: subcmd_matches.value_of("delimiter").map(String::from),
+                timestamp: column("timestamp_index", "timestamp_column"),
+                timestamp_type: subcmd_matches
+                    .value_of("timestamp_type")
+                    .map(|x| x.parse::<timestamp::Timestamp>().unwrap()),
+                timezone: subcmd_matches
+                    .value_of("input_timezone")
+                    .map(|x| x.parse::<Tz>().unwrap()),
+                dst_policy: subcmd_matches
+                    .value_of("dst_policy")
+                    .map(|x| x.parse::<timestamp::DstPolicy>().unwrap()),
+                headers: None,
+                price: column("last_index", "price_column"),
+                volume: column("volume_index", "volume_column"),
+                bid: column("bid_index", "bid_column"),
+                ask: column("ask_index", "ask_column"),
+                aggressor: column("aggressor_index", "aggressor_column"),
+            };
+            // imbalance and run bars sign trades with the tick rule by default,
+            // as de Prado does
+            let classification = match subcmd_matches.value_of("classification") {
+                Some(x) => x.parse::<classify::Classification>().unwrap(),
+                None if bar_type
+                    .is_some_and(|t| t.ends_with("_imbalance") || t.ends_with("_run")) =>
+                {
+                    classify::Classification::Tick
+                }
+                None => classify::Classification::Aggressor,
+            };
+            let bvc_span = match subcmd_matches.value_of("bvc_span") {
                 Some(x) => x.to_owned().parse::<f64>().unwrap(),
-                None => 1.,
+                None => 20.,
             };
-            let timestamp_index = match subcmd_matches.value_of("timestamp_index") {
-                Some(x) => x.to_owned().parse::<usize>().unwrap(),
-                None => 1,
+            let columns = match subcmd_matches.value_of("columns") {
+                Some(x) => x
+                    .split(',')
+                    .map(|column| column.parse::<bars::Column>().unwrap())
+                    .collect(),
+                None => vec![],
             };
-            let last_index = match subcmd_matches.value_of("last_index") {
-                Some(x) => x.to_owned().parse::<usize>().unwrap(),
-                None => 2,
+            let expected_imbalance_span = subcmd_matches
+                .value_of("expected_imbalance_span")
+                .map(|x| x.parse::<f64>().unwrap());
+            let expected_imbalance_per_bar =
+                subcmd_matches.is_present("expected_imbalance_per_bar");
+            let expected_ticks_span = match subcmd_matches.value_of("expected_ticks_span") {
+                Some(x) => x.to_owned().parse::<f64>().unwrap(),
+                None => 20.,
+            };
+            // imbalance bars are sampled the same as mlfinlab's by default,
+            // which has no warmup
+            let warmup_bars = match subcmd_matches.value_of("warmup_bars") {
+                Some(x) => x.to_owned().parse::<u64>().unwrap(),
+                None if bar_type.is_some_and(|bar_type| bar_type.ends_with("_run")) => 10,
+                None => 0,
             };
-            let volume_index = match subcmd_matches.value_of("volume_index") {
+            let min_expected_ticks = subcmd_matches
+                .value_of("min_expected_ticks")
+                .map(|x| x.parse::<f64>().unwrap());
+            let max_expected_ticks = subcmd_matches
+                .value_of("max_expected_ticks")
+                .map(|x| x.parse::<f64>().unwrap());
+            let bars_per_day = subcmd_matches
+                .value_of("bars_per_day")
+                .map(|x| x.parse::<f64>().unwrap());
+            let threshold_lookback_days = match subcmd_matches.value_of("threshold_lookback_days") {
                 Some(x) => x.to_owned().parse::<usize>().unwrap(),
-                None => 3,
+                None => 20,
             };
-            let timestamp_type = match subcmd_matches.value_of("timestamp_type") {
-                Some(x) => match x {
-                    "unix" => bars::Timestamp::Unix,
-                    &_ => bars::Timestamp::IQFeed,
-                },
-                None => bars::Timestamp::IQFeed,
+            let interval = subcmd_matches.value_of("interval").unwrap_or("15m");
+            let settings =
+                settings::Settings::load(subcmd_matches.value_of("settings").unwrap()).unwrap();
+            let calendars =
+                calendar::Calendars::load(subcmd_matches.value_of("calendars").unwrap()).unwrap();
+            let drop_out_of_session = subcmd_matches.is_present("drop_out_of_session");
+            let timezone = match subcmd_matches.value_of("timezone") {
+                Some(x) => x.parse::<Tz>().unwrap(),
+                None => chrono_tz::America::New_York,
             };
-            let delimiter = subcmd_matches.value_of("delimiter").unwrap_or(",");
-            if symbol.ends_with(".txt") {
+            let incremental = subcmd_matches.is_present("incremental");
+            let output = subcmd_matches.value_of("output");
+            let keep = subcmd_matches
+                .value_of("keep")
+                .map(|x| x.parse::<usize>().unwrap());
+            let max_age = subcmd_matches
+                .value_of("max_age")
+                .map(|x| bars::parse_interval(x).unwrap());
+            let process_symbol = |symbol: &String| {
+                let symbol_settings = settings.symbol(symbol);
+                let session_spec = subcmd_matches
+                    .value_of("session")
+                    .or(symbol_settings.session.as_deref());
+                let session = match session_spec {
+                    Some(spec) => Some(settings.session(spec, timezone, &calendars)?),
+                    None => None,
+                };
+                let profile = match subcmd_matches
+                    .value_of("profile")
+                    .or(symbol_settings.profile.as_deref())
+                {
+                    Some(name) => settings.profile(name)?,
+                    None => profile::Profile::default(),
+                };
+                let symbol_tick_threshold = tick_threshold
+                    .or(symbol_settings.tick_threshold)
+                    .unwrap_or(1000);
+                let opts = bars::BarOptions {
+                    profile: input_columns
+                        .clone()
+                        .or(profile)
+                        .or(profile::Profile::iqfeed()),
+                    symbol,
+                    dollar_threshold: dollar_threshold
+                        .or(symbol_settings.dollar_threshold)
+                        .unwrap_or(7000000.0),
+                    tick_threshold: symbol_tick_threshold,
+                    volume_threshold: volume_threshold
+                        .or(symbol_settings.volume_threshold)
+                        .unwrap_or(100000.),
+                    tick_size: tick_size.or(symbol_settings.tick_size).unwrap_or(0.01),
+                    range_ticks: range_ticks.or(symbol_settings.range_ticks).unwrap_or(10),
+                    brick_size: brick_size.or(symbol_settings.brick_size).unwrap_or(1.),
+                    expected_imbalance_span: expected_imbalance_span.unwrap_or(10000.),
+                    expected_imbalance_per_bar,
+                    expected_ticks_span,
+                    warmup_bars,
+                    min_expected_ticks: min_expected_ticks.unwrap_or(0.),
+                    max_expected_ticks,
+                    interval: String::from(interval),
+                    timezone,
+                    session,
+                    drop_out_of_session,
+                    bars_per_day,
+                    threshold_lookback_days,
+                    multiply: multiply.or(symbol_settings.multiply).unwrap_or(1.),
+                    classification,
+                    bvc_span,
+                    columns: columns.clone(),
+                    incremental,
+                    output: output.map(String::from),
+                    retention: bars::Retention { keep, max_age },
+                };
+                // E[b] estimated per bar uses expected_ticks_span instead
+                if expected_imbalance_per_bar
+                    && expected_imbalance_span.is_some()
+                    && bar_type.is_some_and(|bar_type| bar_type.ends_with("_imbalance"))
+                {
+                    return Err(
+                        "--expected_imbalance_span can't be used for imbalance bars \
+                                with --expected_imbalance_per_bar"
+                            .into(),
+                    );
+                }
+                match bar_type {
+                    Some("time") => bars::time_bars(&opts),
+                    Some("dollar") => bars::dollar_bars(&opts),
+                    Some("tick") => bars::tick_bars(&opts),
+                    Some("volume") => bars::volume_bars(&opts),
+                    Some("tick_imbalance") => bars::tick_imbalance_bars(&opts),
+                    Some("volume_imbalance") => bars::volume_imbalance_bars(&opts),
+                    Some("dollar_imbalance") => bars::dollar_imbalance_bars(&opts),
+                    Some("tick_run") => bars::tick_run_bars(&opts),
+                    Some("volume_run") => bars::volume_run_bars(&opts),
+                    Some("dollar_run") => bars::dollar_run_bars(&opts),
+                    Some("range") => bars::range_bars(&opts),
+                    Some("renko") => bars::renko_bars(&opts),
+                    Some(bar_type) => Err(format!(
+                        "Unknown bar type {:?}, expected one of {}",
+                        bar_type,
+                        bars::BAR_TYPES.join(", ")
+                    )
+                    .into()),
+                    None => Err("Must specify bar_type".into()),
+                }
+            };
+            // otherwise every symbol in the list would overwrite the same file
+            if symbol.ends_with(".txt") && output.is_some_and(|output| !output.contains("{symbol}"))
+            {
+                Err(ProcessingError {
+                    errs: vec!["--output must contain {symbol} for a list of symbols".into()],
+                })
+            } else if symbol.ends_with(".txt") {
                 let symbol_file = File::open(symbol).unwrap();
                 let lines = BufReader::new(symbol_file).lines();
                 let errs = lines
-                    .map(|line| match bar_type {
-                        Some("time") => bars::time_bars(&line.unwrap(), &String::from("15")),
-                        Some("dollar") => {
-                            let opts = bars::BarOptions {
-                                delimiter: String::from(delimiter),
-                                symbol: &line.unwrap(),
-                                dollar_threshold: 7000000.0,
-                                multiply,
-                                timestamp_index,
-                                last_index,
-                                volume_index,
-                                timestamp_type,
-                            };
-                            bars::dollar_bars(&opts)
-                        }
-                        None => panic!("Must specify bar_type"),
-                        _ => panic!("Must specify bar_type"),
-                    })
+                    .map(|line| process_symbol(&line.unwrap()))
                     .filter(|res| res.is_err())
                     .flat_map(Err)
                     .collect::<Vec<Box<dyn Error>>>();
@@ -329,40 +801,213 @@ fn main() {
                     Err(ProcessingError { errs })
                 }
             } else {
-                let opts = bars::BarOptions {
-                    delimiter: String::from(delimiter),
-                    symbol: &symbol.to_owned(),
-                    dollar_threshold: 7000000.0,
-                    multiply,
-                    timestamp_index,
-                    last_index,
-                    volume_index,
-                    timestamp_type,
-                };
-                let res = match bar_type {
-                    Some("time") => bars::time_bars(&symbol.to_owned(), &String::from("15")),
-                    Some("dollar") => bars::dollar_bars(&opts),
-                    None => panic!("Must specify bar_type"),
-                    _ => panic!("Must specify bar_type"),
-                };
-                match res {
+                match process_symbol(&symbol.to_owned()) {
                     Ok(_) => Ok(()),
                     Err(e) => Err(ProcessingError { errs: vec![e] }),
                 }
             }
         }
         Some("vol") => {
-            let input_file = matches.value_of("input_file").unwrap();
-            match daily_vol(&input_file.to_owned()) {
+            let subcmd_matches = matches.subcommand_matches("vol").unwrap();
+            let input_file = subcmd_matches.value_of("input_file").unwrap();
+            let timezone = subcmd_matches
+                .value_of("timezone")
+                .unwrap()
+                .parse::<Tz>()
+                .unwrap();
+            match daily_vol(input_file, timezone) {
                 Ok(_) => Ok(()),
                 Err(e) => Err(ProcessingError { errs: vec![e] }),
             }
         }
+        Some("cusum") => {
+            let subcmd_matches = matches.subcommand_matches("cusum").unwrap();
+            let settings =
+                settings::Settings::load(subcmd_matches.value_of("settings").unwrap()).unwrap();
+            let profile = match subcmd_matches.value_of("profile") {
+                Some(name) => settings.profile(name).unwrap(),
+                None => profile::Profile::default(),
+            };
+            // columns default to the profile's, or to those of bars without one
+            let column = |arg: &str, from_profile: &Option<profile::ColumnRef>, default: &str| {
+                match subcmd_matches.value_of(arg) {
+                    Some(x) => x.parse::<profile::ColumnRef>().unwrap(),
+                    None => from_profile
+                        .clone()
+                        .unwrap_or_else(|| profile::ColumnRef::Name(String::from(default))),
+                }
+            };
+            let timestamp_column = column("timestamp_column", &profile.timestamp, "date_time");
+            let price_column = column("price_column", &profile.price, "close");
+            let timestamp_options = profile::Profile {
+                timestamp_type: subcmd_matches
+                    .value_of("timestamp_type")
+                    .map(|x| x.parse::<timestamp::Timestamp>().unwrap()),
+                timezone: subcmd_matches
+                    .value_of("input_timezone")
+                    .map(|x| x.parse::<Tz>().unwrap()),
+                dst_policy: subcmd_matches
+                    .value_of("dst_policy")
+                    .map(|x| x.parse::<timestamp::DstPolicy>().unwrap()),
+                ..profile::Profile::default()
+            };
+            let opts = events::CusumOptions {
+                input_file: subcmd_matches.value_of("input_file").unwrap(),
+                timestamp_column,
+                price_column,
+                profile: timestamp_options.or(profile).or(profile::Profile::iqfeed()),
+                threshold: subcmd_matches
+                    .value_of("threshold")
+                    .unwrap()
+                    .parse::<f64>()
+                    .unwrap(),
+                vol_multiple: subcmd_matches
+                    .value_of("vol_multiple")
+                    .map(|x| x.parse::<f64>().unwrap()),
+                vol_span: subcmd_matches
+                    .value_of("vol_span")
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap(),
+                timezone: subcmd_matches
+                    .value_of("timezone")
+                    .unwrap()
+                    .parse::<Tz>()
+                    .unwrap(),
+            };
+            match events::cusum(&opts) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(ProcessingError { errs: vec![e] }),
+            }
+        }
+        Some("label") => {
+            let label_matches = matches.subcommand_matches("label").unwrap();
+            match label_matches.subcommand() {
+                Some(("triple-barrier", subcmd_matches)) => {
+                    let symbol = subcmd_matches.value_of("symbol").unwrap();
+                    let settings =
+                        settings::Settings::load(subcmd_matches.value_of("settings").unwrap())
+                            .unwrap();
+                    // ticks are read the same way as for bars
+                    let profile = match subcmd_matches
+                        .value_of("profile")
+                        .or(settings.symbol(symbol).profile.as_deref())
+                    {
+                        Some(name) => settings.profile(name).unwrap(),
+                        None => profile::Profile::default(),
+                    };
+                    let bars_file = match subcmd_matches.value_of("bars") {
+                        Some(x) => std::path::PathBuf::from(x),
+                        None => bars::latest_bar_file(
+                            symbol,
+                            subcmd_matches.value_of("bar_type").unwrap(),
+                        )
+                        .unwrap(),
+                    };
+                    let opts = labels::TripleBarrierOptions {
+                        symbol,
+                        bars_file,
+                        events_file: subcmd_matches.value_of("events").unwrap(),
+                        profile: profile.or(profile::Profile::iqfeed()),
+                        timezone: subcmd_matches
+                            .value_of("timezone")
+                            .unwrap()
+                            .parse::<Tz>()
+                            .unwrap(),
+                        profit_take: subcmd_matches
+                            .value_of("profit_take")
+                            .unwrap()
+                            .parse::<f64>()
+                            .unwrap(),
+                        stop_loss: subcmd_matches
+                            .value_of("stop_loss")
+                            .unwrap()
+                            .parse::<f64>()
+                            .unwrap(),
+                        vertical_barrier: bars::parse_interval(
+                            subcmd_matches.value_of("vertical_barrier").unwrap(),
+                        )
+                        .unwrap(),
+                        vol_span: subcmd_matches
+                            .value_of("vol_span")
+                            .unwrap()
+                            .parse::<usize>()
+                            .unwrap(),
+                        sides_file: subcmd_matches.value_of("sides"),
+                        bar_columns: match subcmd_matches.value_of("bar_columns") {
+                            Some(x) => x.split(',').map(String::from).collect(),
+                            None => vec![],
+                        },
+                    };
+                    match labels::triple_barrier(&opts) {
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(ProcessingError { errs: vec![e] }),
+                    }
+                }
+                Some(("trend-scanning", subcmd_matches)) => {
+                    let opts = labels::TrendScanningOptions {
+                        input_file: subcmd_matches.value_of("input_file").unwrap(),
+                        min_horizon: subcmd_matches
+                            .value_of("min_horizon")
+                            .unwrap()
+                            .parse::<usize>()
+                            .unwrap(),
+                        max_horizon: subcmd_matches
+                            .value_of("max_horizon")
+                            .unwrap()
+                            .parse::<usize>()
+                            .unwrap(),
+                    };
+                    match labels::trend_scanning(&opts) {
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(ProcessingError { errs: vec![e] }),
+                    }
+                }
+                Some(("weights", subcmd_matches)) => {
+                    let opts = labels::WeightsOptions {
+                        input_file: subcmd_matches.value_of("input_file").unwrap(),
+                        labels_file: subcmd_matches.value_of("labels").unwrap(),
+                        timezone: subcmd_matches
+                            .value_of("timezone")
+                            .unwrap()
+                            .parse::<Tz>()
+                            .unwrap(),
+                        decay: subcmd_matches
+                            .value_of("decay")
+                            .unwrap()
+                            .parse::<f64>()
+                            .unwrap(),
+                        concurrency_file: subcmd_matches.value_of("concurrency"),
+                    };
+                    match labels::weights(&opts) {
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(ProcessingError { errs: vec![e] }),
+                    }
+                }
+                _ => {
+                    app.print_help().unwrap();
+                    std::process::exit(1);
+                }
+            }
+        }
         Some("ticks") => {
             let subcmd_matches = matches.subcommand_matches("ticks").unwrap();
             let symbol = subcmd_matches.value_of("symbol").unwrap();
             let output_dir = subcmd_matches.value_of("output_dir").unwrap();
             let no_mkt_hours = subcmd_matches.is_present("no_mkt_hours");
+            let calendars =
+                calendar::Calendars::load(subcmd_matches.value_of("calendars").unwrap()).unwrap();
+            // Due to limited history, ticks shouldn't be gathered for new
+            // symbols during NYC market hours.
+            let market_hours = if no_mkt_hours {
+                None
+            } else {
+                Some(
+                    calendars
+                        .session("nyse:rth", chrono_tz::America::New_York)
+                        .unwrap(),
+                )
+            };
 
             if check_iqfeed_health() != 0 {
                 panic!("No iqfeed connection")
@@ -374,7 +1019,7 @@ fn main() {
                 let errs = lines
                     .map(|line| {
                         debug!(line = ?line.as_ref().unwrap().clone(), output_dir = ?output_dir, "calling iqfeed ticks");
-                        ticks::iqfeed_ticks(&line.unwrap(), &output_dir.to_owned(), no_mkt_hours)
+                        ticks::iqfeed_ticks(&line.unwrap(), output_dir, market_hours.as_ref())
                     })
                     .filter(|res| res.is_err())
                     .flat_map(Err)
@@ -385,8 +1030,7 @@ fn main() {
                     Err(ProcessingError { errs })
                 }
             } else {
-                match ticks::iqfeed_ticks(&symbol.to_owned(), &output_dir.to_owned(), no_mkt_hours)
-                {
+                match ticks::iqfeed_ticks(symbol, output_dir, market_hours.as_ref()) {
                     Ok(_) => Ok(()),
                     Err(e) => Err(ProcessingError { errs: vec![e] }),
                 }
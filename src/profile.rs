@@ -0,0 +1,237 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::timestamp::{DstPolicy, Timestamp};
+
+// A column of a tick file, either by its name in the header or by its index.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(usize),
+    Name(String),
+}
+
+impl ColumnRef {
+    pub fn index(&self, headers: &csv::StringRecord) -> Result<usize, Box<dyn Error>> {
+        match self {
+            ColumnRef::Index(index) => Ok(*index),
+            ColumnRef::Name(name) => column_index(headers, name),
+        }
+    }
+}
+
+pub fn column_index(headers: &csv::StringRecord, name: &str) -> Result<usize, Box<dyn Error>> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| format!("No column named {:?}", name).into())
+}
+
+// Indexes are written as #3 to tell them apart from names.
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnRef::Index(index) => write!(f, "#{}", index),
+            ColumnRef::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl FromStr for ColumnRef {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<ColumnRef, Box<dyn Error>> {
+        match s.strip_prefix('#') {
+            Some(index) => Ok(ColumnRef::Index(index.parse()?)),
+            None => Ok(ColumnRef::Name(String::from(s))),
+        }
+    }
+}
+
+// How to read the tick files from a particular vendor, e.g.
+//
+// [profiles.kraken]
+// delimiter = ","
+// timestamp = "time"
+// timestamp_type = "unix_ms"
+// timezone = "UTC"
+// price = "price"
+// volume = "qty"
+//
+// Anything left unset falls back to the IQFeed layout that `feat ticks`
+// writes.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Profile {
+    pub delimiter: Option<String>,
+    // whether tick files have a header, without one columns can only be given
+    // by index
+    pub headers: Option<bool>,
+    pub timestamp: Option<ColumnRef>,
+    pub timestamp_type: Option<Timestamp>,
+    // what timestamps without an offset are local to
+    pub timezone: Option<Tz>,
+    pub dst_policy: Option<DstPolicy>,
+    pub price: Option<ColumnRef>,
+    pub volume: Option<ColumnRef>,
+    pub bid: Option<ColumnRef>,
+    pub ask: Option<ColumnRef>,
+    pub aggressor: Option<ColumnRef>,
+}
+
+impl Profile {
+    pub fn iqfeed() -> Profile {
+        let name = |name: &str| Some(ColumnRef::Name(String::from(name)));
+        Profile {
+            delimiter: Some(String::from(",")),
+            headers: Some(true),
+            timestamp: name("date_time"),
+            timestamp_type: Some(Timestamp::IQFeed),
+            timezone: Some(chrono_tz::America::New_York),
+            dst_policy: Some(DstPolicy::Error),
+            price: name("last"),
+            volume: name("last_size"),
+            bid: name("bid"),
+            ask: name("ask"),
+            aggressor: name("trade_aggressor"),
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<Profile> {
+        let name_ref = |name: &str| Some(ColumnRef::Name(String::from(name)));
+        match name {
+            "iqfeed" => Some(Profile::iqfeed()),
+            // trades from https://docs.tardis.dev/downloadable-csv-files
+            "tardis" => Some(Profile {
+                timestamp: name_ref("timestamp"),
+                timestamp_type: Some(Timestamp::Micros),
+                timezone: Some(chrono_tz::UTC),
+                price: name_ref("price"),
+                volume: name_ref("amount"),
+                aggressor: name_ref("side"),
+                ..Profile::default()
+            }),
+            // trades from https://data.binance.vision, which don't have a
+            // header. is_buyer_maker isn't used as the aggressor since it
+            // isn't reported for every market.
+            "binance" => Some(Profile {
+                headers: Some(false),
+                timestamp: Some(ColumnRef::Index(4)),
+                timestamp_type: Some(Timestamp::Millis),
+                timezone: Some(chrono_tz::UTC),
+                price: Some(ColumnRef::Index(1)),
+                volume: Some(ColumnRef::Index(2)),
+                ..Profile::default()
+            }),
+            _ => None,
+        }
+    }
+
+    // Fills in anything unset from other.
+    pub fn or(self, other: Profile) -> Profile {
+        Profile {
+            delimiter: self.delimiter.or(other.delimiter),
+            headers: self.headers.or(other.headers),
+            timestamp: self.timestamp.or(other.timestamp),
+            timestamp_type: self.timestamp_type.or(other.timestamp_type),
+            timezone: self.timezone.or(other.timezone),
+            dst_policy: self.dst_policy.or(other.dst_policy),
+            price: self.price.or(other.price),
+            volume: self.volume.or(other.volume),
+            bid: self.bid.or(other.bid),
+            ask: self.ask.or(other.ask),
+            aggressor: self.aggressor.or(other.aggressor),
+        }
+    }
+
+    pub fn delimiter(&self) -> u8 {
+        self.delimiter.as_deref().unwrap_or(",").as_bytes()[0]
+    }
+
+    pub fn headers(&self) -> bool {
+        self.headers.unwrap_or(true)
+    }
+
+    pub fn timestamp_type(&self) -> &Timestamp {
+        self.timestamp_type.as_ref().unwrap_or(&Timestamp::IQFeed)
+    }
+
+    pub fn timezone(&self) -> Tz {
+        self.timezone.unwrap_or(chrono_tz::America::New_York)
+    }
+
+    pub fn dst_policy(&self) -> DstPolicy {
+        self.dst_policy.unwrap_or(DstPolicy::Error)
+    }
+
+    // Parses a tick's timestamp, or None if it should be dropped.
+    pub fn parse_timestamp(&self, s: &str) -> Result<Option<DateTime<Tz>>, Box<dyn Error>> {
+        self.timestamp_type()
+            .parse(s, self.timezone(), self.dst_policy())
+    }
+
+    // Looks up the index of each column in a tick file with headers. The
+    // timestamp, price and volume are required, while the quote and aggressor
+    // are only used if the file has them.
+    pub fn indexes(&self, headers: &csv::StringRecord) -> Result<ColumnIndexes, Box<dyn Error>> {
+        let required = |column: &Option<ColumnRef>, field: &str| match column {
+            Some(column) => column.index(headers),
+            None => Err(format!("No {} column given", field).into()),
+        };
+        let optional = |column: &Option<ColumnRef>| {
+            column
+                .as_ref()
+                .and_then(|column| column.index(headers).ok())
+        };
+        Ok(ColumnIndexes {
+            timestamp: required(&self.timestamp, "timestamp")?,
+            price: required(&self.price, "price")?,
+            volume: required(&self.volume, "volume")?,
+            bid: optional(&self.bid),
+            ask: optional(&self.ask),
+            aggressor: optional(&self.aggressor),
+        })
+    }
+}
+
+// Where each field is in the records of a particular tick file.
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnIndexes {
+    pub timestamp: usize,
+    pub price: usize,
+    pub volume: usize,
+    pub bid: Option<usize>,
+    pub ask: Option<usize>,
+    pub aggressor: Option<usize>,
+}
+
+// e.g. "delimiter=, headers=true timestamp=date_time ...", for telling
+// profiles apart. Unset columns are written as none.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let column = |column: &Option<ColumnRef>| {
+            column
+                .as_ref()
+                .map_or(String::from("none"), |column| column.to_string())
+        };
+        write!(
+            f,
+            "delimiter={} headers={} timestamp={} timestamp_type={} timezone={} dst_policy={} \
+             price={} volume={} bid={} ask={} aggressor={}",
+            self.delimiter() as char,
+            self.headers(),
+            column(&self.timestamp),
+            self.timestamp_type(),
+            self.timezone().name(),
+            self.dst_policy(),
+            column(&self.price),
+            column(&self.volume),
+            column(&self.bid),
+            column(&self.ask),
+            column(&self.aggressor),
+        )
+    }
+}
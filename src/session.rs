@@ -0,0 +1,330 @@
+use crate::calendar::Calendar;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use std::error::Error;
+use std::fmt;
+
+// A daily window of trading hours in local time. Windows whose end is before
+// their start wrap past midnight (e.g. 18:00-17:00 for CME Globex), in which
+// case they belong to the trading day they end on.
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl Window {
+    // Parses windows like 09:30-16:00
+    fn parse(s: &str) -> Result<Window, Box<dyn Error>> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Session window {:?} should look like 09:30-16:00", s))?;
+        Ok(Window {
+            start: NaiveTime::parse_from_str(start.trim(), "%H:%M")?,
+            end: NaiveTime::parse_from_str(end.trim(), "%H:%M")?,
+        })
+    }
+
+    fn overnight(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+// Trading hours for a symbol, used to reset bars at session boundaries and to
+// optionally drop ticks printed outside of the session.
+#[derive(Debug, Clone)]
+pub struct Session {
+    calendar: Calendar,
+    windows: Vec<Window>,
+}
+
+impl Session {
+    pub fn new(calendar: Calendar, windows: &[String]) -> Result<Session, Box<dyn Error>> {
+        Ok(Session {
+            calendar,
+            windows: windows
+                .iter()
+                .map(|w| Window::parse(w))
+                .collect::<Result<Vec<Window>, Box<dyn Error>>>()?,
+        })
+    }
+
+    fn localize(&self, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Tz>> {
+        self.calendar
+            .timezone
+            .from_local_datetime(&date.and_time(time))
+            .earliest()
+    }
+
+    // The opens and closes of the windows that close on day, if it's a
+    // trading day.
+    fn boundaries_closing_on(&self, day: NaiveDate) -> Vec<(DateTime<Tz>, bool)> {
+        let mut boundaries = vec![];
+        if !self.calendar.is_trading_day(day) {
+            return boundaries;
+        }
+        for window in &self.windows {
+            let open_day = if window.overnight() {
+                day - Duration::days(1)
+            } else {
+                day
+            };
+            let end = match self.calendar.early_close(day) {
+                Some(early_close) if early_close < window.end => early_close,
+                _ => window.end,
+            };
+            // the early close is before this window even opens
+            if !window.overnight() && end <= window.start {
+                continue;
+            }
+            if let Some(open) = self.localize(open_day, window.start) {
+                boundaries.push((open, true));
+            }
+            if let Some(close) = self.localize(day, end) {
+                boundaries.push((close, false));
+            }
+        }
+        boundaries
+    }
+
+    // The most recent session open or close at or before date_time, and
+    // whether date_time is in the session. Ticks between the same pair of
+    // boundaries belong in the same bars.
+    pub fn boundary<T: TimeZone>(&self, date_time: &DateTime<T>) -> Option<(DateTime<Tz>, bool)> {
+        let date_time = date_time.with_timezone(&self.calendar.timezone);
+        let date = date_time.date_naive();
+        let mut latest: Option<(DateTime<Tz>, bool)> = None;
+        // far enough back to get past weekends and holidays
+        for days_back in -1..10 {
+            for (boundary, is_open) in self.boundaries_closing_on(date - Duration::days(days_back))
+            {
+                if boundary <= date_time && latest.is_none_or(|(latest, _)| boundary > latest) {
+                    latest = Some((boundary, is_open));
+                }
+            }
+        }
+        latest
+    }
+
+    // The first session open or close after date_time.
+    pub fn next_boundary<T: TimeZone>(&self, date_time: &DateTime<T>) -> Option<DateTime<Tz>> {
+        let date_time = date_time.with_timezone(&self.calendar.timezone);
+        let date = date_time.date_naive();
+        let mut next: Option<DateTime<Tz>> = None;
+        for days_ahead in 0..11 {
+            for (boundary, _) in self.boundaries_closing_on(date + Duration::days(days_ahead)) {
+                if boundary > date_time && next.is_none_or(|next| boundary < next) {
+                    next = Some(boundary);
+                }
+            }
+        }
+        next
+    }
+}
+
+// Remembers the boundary of the last tick along with the span of time it
+// holds for, so the session is only searched again once a tick falls outside
+// of it, normally by passing the next open or close.
+pub struct BoundaryCache<'s> {
+    session: &'s Session,
+    boundary: Option<(DateTime<Tz>, bool)>,
+    // from and until
+    valid: Option<(DateTime<Tz>, DateTime<Tz>)>,
+}
+
+impl<'s> BoundaryCache<'s> {
+    pub fn new(session: &'s Session) -> BoundaryCache<'s> {
+        BoundaryCache {
+            session,
+            boundary: None,
+            valid: None,
+        }
+    }
+
+    // The same as Session::boundary.
+    pub fn boundary<T: TimeZone>(
+        &mut self,
+        date_time: &DateTime<T>,
+    ) -> Option<(DateTime<Tz>, bool)> {
+        let date_time = date_time.with_timezone(&self.session.calendar.timezone);
+        if let Some((from, until)) = self.valid {
+            if from <= date_time && date_time < until {
+                return self.boundary;
+            }
+        }
+        self.boundary = self.session.boundary(&date_time);
+        let from = self.boundary.map_or(date_time, |(boundary, _)| boundary);
+        self.valid = self
+            .session
+            .next_boundary(&date_time)
+            .map(|until| (from, until));
+        self.boundary
+    }
+}
+
+// e.g. "America/New_York 09:30-16:00", for telling sessions apart.
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.calendar.timezone.name())?;
+        for window in &self.windows {
+            write!(
+                f,
+                " {}-{}",
+                window.start.format("%H:%M"),
+                window.end.format("%H:%M")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calendar::Calendars;
+    use chrono::{NaiveDateTime, Utc};
+
+    fn session(spec: &str) -> Session {
+        let calendars = Calendars::parse(
+            r#"
+            [nyse]
+            holidays = ["2021-11-25"]
+
+            [nyse.early_closes]
+            "2021-11-26" = "13:00"
+            "#,
+        )
+        .unwrap();
+        calendars
+            .session(spec, chrono_tz::America::New_York)
+            .unwrap()
+    }
+
+    fn local(tz: Tz, s: &str) -> DateTime<Tz> {
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        tz.from_local_datetime(&naive).unwrap()
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        Utc.from_utc_datetime(&naive)
+    }
+
+    #[test]
+    fn globex_opens_on_sunday_evening() {
+        let globex = session("globex");
+        let chicago = |s| local(chrono_tz::America::Chicago, s);
+        let friday_close = Some((chicago("2021-10-15 16:00"), false));
+        let sunday_open = Some((chicago("2021-10-17 17:00"), true));
+        // no open on Friday evening, since Saturday doesn't trade
+        assert_eq!(globex.boundary(&chicago("2021-10-15 17:30")), friday_close);
+        assert_eq!(globex.boundary(&chicago("2021-10-16 12:00")), friday_close);
+        assert_eq!(globex.boundary(&chicago("2021-10-17 16:59")), friday_close);
+        assert_eq!(globex.boundary(&chicago("2021-10-17 17:00")), sunday_open);
+        assert_eq!(globex.boundary(&chicago("2021-10-18 09:00")), sunday_open);
+        assert_eq!(
+            globex.boundary(&chicago("2021-10-18 16:30")),
+            Some((chicago("2021-10-18 16:00"), false))
+        );
+        assert_eq!(
+            globex.next_boundary(&chicago("2021-10-15 17:30")),
+            Some(chicago("2021-10-17 17:00"))
+        );
+    }
+
+    #[test]
+    fn nyse_closes_early_on_half_days() {
+        let rth = session("rth");
+        let new_york = |s| local(chrono_tz::America::New_York, s);
+        // Thanksgiving is a holiday, so the last boundary is the day before
+        assert_eq!(
+            rth.boundary(&new_york("2021-11-25 10:00")),
+            Some((new_york("2021-11-24 16:00"), false))
+        );
+        assert_eq!(
+            rth.boundary(&new_york("2021-11-26 12:59")),
+            Some((new_york("2021-11-26 09:30"), true))
+        );
+        assert_eq!(
+            rth.boundary(&new_york("2021-11-26 13:00")),
+            Some((new_york("2021-11-26 13:00"), false))
+        );
+        assert_eq!(
+            rth.boundary(&new_york("2021-11-26 15:00")),
+            Some((new_york("2021-11-26 13:00"), false))
+        );
+        assert_eq!(
+            rth.next_boundary(&new_york("2021-11-26 10:00")),
+            Some(new_york("2021-11-26 13:00"))
+        );
+    }
+
+    #[test]
+    fn boundaries_follow_dst_changes() {
+        let rth = session("rth");
+        let globex = session("globex");
+        // the Friday before spring forward closes at 21:00 UTC, Monday
+        // opens at 13:30 UTC instead of 14:30
+        assert_eq!(
+            rth.boundary(&utc("2021-03-15 13:29")),
+            Some((
+                utc("2021-03-12 21:00").with_timezone(&rth.calendar.timezone),
+                false
+            ))
+        );
+        assert_eq!(
+            rth.boundary(&utc("2021-03-15 13:30")),
+            Some((
+                utc("2021-03-15 13:30").with_timezone(&rth.calendar.timezone),
+                true
+            ))
+        );
+        // Globex opens at 17:00 Chicago time on the day the clocks change,
+        // which is 22:00 UTC, the same as the 16:00 CST close on Friday
+        assert_eq!(
+            globex.boundary(&utc("2021-03-14 21:59")),
+            Some((
+                utc("2021-03-12 22:00").with_timezone(&globex.calendar.timezone),
+                false
+            ))
+        );
+        assert_eq!(
+            globex.boundary(&utc("2021-03-14 22:00")),
+            Some((
+                utc("2021-03-14 22:00").with_timezone(&globex.calendar.timezone),
+                true
+            ))
+        );
+        // and in the fall the Monday open is an hour later in UTC
+        assert_eq!(
+            rth.boundary(&utc("2021-11-08 14:29")),
+            Some((
+                utc("2021-11-05 20:00").with_timezone(&rth.calendar.timezone),
+                false
+            ))
+        );
+        assert_eq!(
+            rth.next_boundary(&utc("2021-11-08 14:29")),
+            Some(utc("2021-11-08 14:30").with_timezone(&rth.calendar.timezone))
+        );
+    }
+
+    #[test]
+    fn cache_agrees_with_boundary() {
+        for spec in &["rth", "globex", "crypto", "09:30-12:00,13:00-16:00"] {
+            let session = session(spec);
+            let mut cache = BoundaryCache::new(&session);
+            let mut date_time = utc("2021-10-29 00:00");
+            while date_time < utc("2021-12-01 00:00") {
+                assert_eq!(
+                    cache.boundary(&date_time),
+                    session.boundary(&date_time),
+                    "{} at {}",
+                    spec,
+                    date_time
+                );
+                date_time += Duration::minutes(7);
+            }
+        }
+    }
+}
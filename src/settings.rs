@@ -0,0 +1,117 @@
+use crate::calendar::{Calendar, Calendars};
+use crate::profile::Profile;
+use crate::session::Session;
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+#[derive(Debug, Deserialize)]
+struct SessionSettings {
+    calendar: Option<String>,
+    timezone: Option<String>,
+    windows: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SymbolSettings {
+    pub profile: Option<String>,
+    pub session: Option<String>,
+    pub multiply: Option<f64>,
+    pub dollar_threshold: Option<f64>,
+    pub tick_threshold: Option<u64>,
+    pub volume_threshold: Option<f64>,
+    pub tick_size: Option<f64>,
+    pub range_ticks: Option<u64>,
+    pub brick_size: Option<f64>,
+}
+
+// Per symbol settings for processing bars, e.g.
+//
+// [sessions.lunch_break]
+// timezone = "Asia/Tokyo"
+// windows = ["09:00-11:30", "12:30-15:00"]
+//
+// [sessions.morning]
+// calendar = "nyse"
+// windows = ["09:30-12:00"]
+//
+// [profiles.kraken]
+// timestamp = "time"
+// price = "price"
+// volume = "qty"
+//
+// [symbols.TSLA]
+// session = "rth"
+// dollar_threshold = 7000000.0
+//
+// [symbols.XBTUSD]
+// profile = "kraken"
+//
+// [symbols."@ES#C"]
+// multiply = 50.0
+// dollar_threshold = 50000000.0
+#[derive(Debug, Default, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    sessions: HashMap<String, SessionSettings>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    symbols: HashMap<String, SymbolSettings>,
+    #[serde(skip)]
+    defaults: SymbolSettings,
+}
+
+impl Settings {
+    // Loads settings from path, which doesn't need to exist.
+    pub fn load(path: &str) -> Result<Settings, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(toml::from_str(&content)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Settings::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    // Settings for symbol, which are all unset if it isn't in the file.
+    pub fn symbol(&self, symbol: &str) -> &SymbolSettings {
+        self.symbols.get(symbol).unwrap_or(&self.defaults)
+    }
+
+    // Looks up an input profile defined in the settings file, falling back to
+    // the built in profiles.
+    pub fn profile(&self, name: &str) -> Result<Profile, Box<dyn Error>> {
+        self.profiles
+            .get(name)
+            .cloned()
+            .or_else(|| Profile::builtin(name))
+            .ok_or_else(|| format!("Unknown input profile {:?}", name).into())
+    }
+
+    // Resolves a session defined in the settings file, falling back to the
+    // sessions defined by calendars and finally to a list of windows in
+    // timezone. Sessions in the settings file trade on weekdays unless they
+    // name a calendar.
+    pub fn session(
+        &self,
+        spec: &str,
+        timezone: Tz,
+        calendars: &Calendars,
+    ) -> Result<Session, Box<dyn Error>> {
+        match self.sessions.get(spec) {
+            Some(s) => {
+                let mut calendar = match &s.calendar {
+                    Some(calendar) => calendars.get(calendar)?.clone(),
+                    None => Calendar::weekdays(timezone),
+                };
+                if let Some(timezone) = &s.timezone {
+                    calendar.timezone = timezone.parse::<Tz>()?;
+                }
+                Session::new(calendar, &s.windows)
+            }
+            None => calendars.session(spec, timezone),
+        }
+    }
+}
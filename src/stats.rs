@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+// Exponentially weighted moving average, parameterized by span in the same
+// way as pandas' ewm(span=...), i.e. alpha = 2 / (span + 1).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Ewma {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    pub fn new(span: f64) -> Ewma {
+        Ewma {
+            alpha: 2. / (span + 1.),
+            value: None,
+        }
+    }
+
+    // Starts the average at initial instead of at the first observation.
+    pub fn with_initial(span: f64, initial: f64) -> Ewma {
+        Ewma {
+            value: Some(initial),
+            ..Ewma::new(span)
+        }
+    }
+
+    pub fn update(&mut self, x: f64) -> f64 {
+        let value = match self.value {
+            Some(v) => (x * self.alpha) + (v * (1. - self.alpha)),
+            None => x,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+// The last few values of a series, for EWMAs over only those values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Window {
+    size: usize,
+    values: VecDeque<f64>,
+}
+
+impl Window {
+    pub fn new(size: usize) -> Window {
+        Window {
+            size,
+            values: VecDeque::with_capacity(size),
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if self.values.len() == self.size {
+            self.values.pop_front();
+        }
+        self.values.push_back(x);
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    // EWMA of the values in the window, with weights adjusted for there being
+    // no values before them as in pandas' ewm(span=...).mean() and mlfinlab's
+    // ewma, i.e. sum((1 - alpha)^i * x[n - i]) / sum((1 - alpha)^i).
+    pub fn ewm_mean(&self, span: f64) -> Option<f64> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let decay = 1. - 2. / (span + 1.);
+        let (sum, weight) = self.values.iter().fold((0., 0.), |(sum, weight), x| {
+            (sum * decay + x, weight * decay + 1.)
+        });
+        Some(sum / weight)
+    }
+}
+
+// Exponentially weighted standard deviation, using the same span convention
+// as Ewma.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EwmStd {
+    alpha: f64,
+    mean: Option<f64>,
+    var: f64,
+    n: usize,
+}
+
+impl EwmStd {
+    pub fn new(span: f64) -> EwmStd {
+        EwmStd {
+            alpha: 2. / (span + 1.),
+            mean: None,
+            var: 0.,
+            n: 0,
+        }
+    }
+
+    pub fn update(&mut self, x: f64) {
+        self.n += 1;
+        match self.mean {
+            Some(mean) => {
+                let diff = x - mean;
+                let incr = self.alpha * diff;
+                self.mean = Some(mean + incr);
+                self.var = (1. - self.alpha) * (self.var + diff * incr);
+            }
+            None => self.mean = Some(x),
+        }
+    }
+
+    // Number of observations so far.
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn value(&self) -> Option<f64> {
+        self.mean.map(|_| self.var.sqrt())
+    }
+}
+
+// Standard normal cumulative distribution function, using the approximation
+// of erf from Abramowitz and Stegun 7.1.26, which is accurate to about 1e-7.
+pub fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1. / (1. + 0.3275911 * z);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1. - poly * (-z * z).exp();
+    if x >= 0. {
+        (1. + erf) / 2.
+    } else {
+        (1. - erf) / 2.
+    }
+}
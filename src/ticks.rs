@@ -1,9 +1,5 @@
 use chrono::DateTime;
-use chrono::Datelike;
-use chrono::TimeZone;
-use chrono::Timelike;
 use chrono::Utc;
-use chrono::Weekday;
 use chrono_tz::America::New_York;
 use chrono_tz::Tz;
 use serde::{Deserialize, Serialize};
@@ -16,6 +12,8 @@ use std::net::TcpStream;
 use std::path::Path;
 use tracing::{self, debug, error, info};
 
+use crate::session::Session;
+
 #[derive(Serialize, Deserialize)]
 struct IQFeedTickMetaData {
     #[serde(with = "crate::iqfeed_date_time")]
@@ -37,7 +35,11 @@ impl fmt::Display for IQFeedNoDataError {
 impl Error for IQFeedNoDataError {}
 
 // call iqfeed for ticks
-pub fn iqfeed_ticks(symbol: &str, out_dir: &str, no_mkt_hours: bool) -> Result<(), Box<dyn Error>> {
+pub fn iqfeed_ticks(
+    symbol: &str,
+    out_dir: &str,
+    market_hours: Option<&Session>,
+) -> Result<(), Box<dyn Error>> {
     let out_dir_path = Path::new(out_dir).join(symbol);
     fs::create_dir_all(out_dir_path.to_str().unwrap())?;
     let now_dt = Utc::now().with_timezone(&New_York);
@@ -62,13 +64,10 @@ pub fn iqfeed_ticks(symbol: &str, out_dir: &str, no_mkt_hours: bool) -> Result<(
         min_date_time = format!("{}", meta_cfg.min_date_time.format("%Y%m%d %H%M%S"));
         max_date_time = format!("{}", meta_cfg.max_date_time.format("%Y%m%d %H%M%S"));
     } else {
-        let naive_dt = Utc::now().naive_utc();
-        let ny_dt = New_York.from_utc_datetime(&naive_dt);
-        if !no_mkt_hours && ny_dt.weekday() != Weekday::Sat
-            && ny_dt.weekday() != Weekday::Sun
-            && ny_dt.hour() > 9 // todo: technically 9:30, but whatever
-            && ny_dt.hour() < 16
-        {
+        let in_market_hours = market_hours
+            .and_then(|session| session.boundary(&Utc::now()))
+            .is_some_and(|(_, in_session)| in_session);
+        if in_market_hours {
             return Err("Due to limited history, ticks should not be gathered \
                         for new symbols during NYC market hours."
                 .into());
@@ -88,7 +87,7 @@ pub fn iqfeed_ticks(symbol: &str, out_dir: &str, no_mkt_hours: bool) -> Result<(
     );
 
     let mut stream = TcpStream::connect("127.0.0.1:9100")?;
-    stream.write_all("S,SET PROTOCOL,5.1\r\n".as_bytes())?;
+    stream.write_all("S,SET PROTOCOL,6.2\r\n".as_bytes())?;
 
     debug!(
         request = format!("HTT,{},{},{},,,,1,{}\r\n", symbol, max_date_time, "", 1).as_str(),
@@ -102,12 +101,18 @@ pub fn iqfeed_ticks(symbol: &str, out_dir: &str, no_mkt_hours: bool) -> Result<(
     let mut lines = io::BufReader::new(stream).lines();
     let mut out_file_buf = io::BufWriter::new(out_file);
 
-    // First line is S,CURRENT_PROTOCOL,5.1
+    // First line is S,CURRENT PROTOCOL,6.2
     // Discard
     let _current_proto_header = lines.next();
-    writeln!(out_file_buf, "request_id,date_time,last,last_size,total_volume,bid,ask,tick_id,basis_for_last,trade_market_center,trade_conditions,trade_aggressor")?;
+    // since protocol 6.0 every tick has an LH message type after the request
+    // id, and the timestamp has microseconds
+    writeln!(out_file_buf, "request_id,message_type,date_time,last,last_size,total_volume,bid,ask,tick_id,basis_for_last,trade_market_center,trade_conditions,trade_aggressor,day_code")?;
 
     let mut n_ticks = 0;
+    // ticks are new if they're later than the last tick of the previous pull,
+    // not the last tick written, so that every tick of a run at the same
+    // microsecond is kept
+    let watermark = meta_cfg.max_date_time;
 
     for line_res in lines {
         let line = line_res?;
@@ -123,13 +128,15 @@ pub fn iqfeed_ticks(symbol: &str, out_dir: &str, no_mkt_hours: bool) -> Result<(
         if &v[1].to_owned() == "!ENDMSG!" {
             break;
         }
-        let tick_date_time = crate::iqfeed_date_time::parse(&v[1].to_owned())?;
-        if tick_date_time > meta_cfg.max_date_time {
-            meta_cfg.max_date_time = tick_date_time;
+        let tick_date_time = crate::iqfeed_date_time::parse(v[2])?;
+        if tick_date_time > watermark {
             out_file_buf.write_all(line.as_bytes())?;
             out_file_buf.write_all(b"\n")?;
             n_ticks += 1;
         }
+        if tick_date_time > meta_cfg.max_date_time {
+            meta_cfg.max_date_time = tick_date_time;
+        }
         if tick_date_time < meta_cfg.min_date_time {
             meta_cfg.min_date_time = tick_date_time;
         }
@@ -0,0 +1,352 @@
+use chrono::offset::LocalResult;
+use chrono::{DateTime, Duration, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+// How the timestamps of ticks are formatted.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Timestamp {
+    // e.g. 2021-09-13 09:30:00.123456, in local time
+    IQFeed,
+    // e.g. 2021-09-13T13:30:00.123Z or 2021-09-13 09:30:00-04:00, in local
+    // time when there's no offset
+    Iso8601,
+    // since the Unix epoch, optionally with a fractional part
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+    // strftime format, e.g. %Y-%m-%dT%H:%M:%S%.f, in local time unless it has
+    // an offset (%z)
+    Format(String),
+}
+
+impl FromStr for Timestamp {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Timestamp, Box<dyn Error>> {
+        match s {
+            // string was the default before timestamp types did anything
+            "iqfeed" | "string" => Ok(Timestamp::IQFeed),
+            "iso8601" | "rfc3339" => Ok(Timestamp::Iso8601),
+            "unix" | "unix_s" => Ok(Timestamp::Seconds),
+            "unix_ms" => Ok(Timestamp::Millis),
+            "unix_us" => Ok(Timestamp::Micros),
+            "unix_ns" => Ok(Timestamp::Nanos),
+            _ if s.contains('%') => Ok(Timestamp::Format(String::from(s))),
+            _ => Err(format!("Unknown timestamp type {:?}", s).into()),
+        }
+    }
+}
+
+// The same names FromStr takes.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Timestamp::IQFeed => write!(f, "iqfeed"),
+            Timestamp::Iso8601 => write!(f, "iso8601"),
+            Timestamp::Seconds => write!(f, "unix_s"),
+            Timestamp::Millis => write!(f, "unix_ms"),
+            Timestamp::Micros => write!(f, "unix_us"),
+            Timestamp::Nanos => write!(f, "unix_ns"),
+            Timestamp::Format(format) => write!(f, "{}", format),
+        }
+    }
+}
+
+impl TryFrom<String> for Timestamp {
+    type Error = Box<dyn Error>;
+
+    fn try_from(s: String) -> Result<Timestamp, Box<dyn Error>> {
+        s.parse()
+    }
+}
+
+// What to do with local times that happen twice when clocks go back, or not at
+// all when they go forward.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum DstPolicy {
+    Error,
+    // the first or second of the two times when clocks go back. Skipped times
+    // are taken to be in the offset from before clocks went forward, so they
+    // end up shifted forward by the gap.
+    Earliest,
+    Latest,
+    // skip ticks at either
+    Drop,
+}
+
+impl FromStr for DstPolicy {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<DstPolicy, Box<dyn Error>> {
+        match s {
+            "error" => Ok(DstPolicy::Error),
+            "earliest" => Ok(DstPolicy::Earliest),
+            "latest" => Ok(DstPolicy::Latest),
+            "drop" => Ok(DstPolicy::Drop),
+            _ => Err(format!("Unknown DST policy {:?}", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for DstPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DstPolicy::Error => write!(f, "error"),
+            DstPolicy::Earliest => write!(f, "earliest"),
+            DstPolicy::Latest => write!(f, "latest"),
+            DstPolicy::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+impl TryFrom<String> for DstPolicy {
+    type Error = Box<dyn Error>;
+
+    fn try_from(s: String) -> Result<DstPolicy, Box<dyn Error>> {
+        s.parse()
+    }
+}
+
+// Resolves a local time in timezone, or None if policy drops it.
+pub fn localize(
+    naive: &NaiveDateTime,
+    timezone: Tz,
+    policy: DstPolicy,
+) -> Result<Option<DateTime<Tz>>, Box<dyn Error>> {
+    match (timezone.from_local_datetime(naive), policy) {
+        (LocalResult::Single(date_time), _) => Ok(Some(date_time)),
+        (_, DstPolicy::Drop) => Ok(None),
+        (LocalResult::Ambiguous(earliest, _), DstPolicy::Earliest) => Ok(Some(earliest)),
+        (LocalResult::Ambiguous(_, latest), DstPolicy::Latest) => Ok(Some(latest)),
+        (LocalResult::Ambiguous(_, _), DstPolicy::Error) => Err(format!(
+            "Ambiguous local time {} in {}, see --dst_policy",
+            naive,
+            timezone.name()
+        )
+        .into()),
+        (LocalResult::None, DstPolicy::Error) => Err(format!(
+            "Nonexistent local time {} in {}, see --dst_policy",
+            naive,
+            timezone.name()
+        )
+        .into()),
+        (LocalResult::None, _) => {
+            let before = timezone
+                .offset_from_utc_datetime(&(*naive - Duration::days(1)))
+                .fix();
+            let utc = *naive - Duration::seconds(before.local_minus_utc() as i64);
+            Ok(Some(timezone.from_utc_datetime(&utc)))
+        }
+    }
+}
+
+// strftime specifiers for an offset from UTC.
+const OFFSETS: [&str; 3] = ["%z", "%:z", "%#z"];
+
+// Naive formats accepted for ISO-8601 timestamps without an offset.
+const ISO8601_LOCAL: [&str; 2] = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"];
+
+impl Timestamp {
+    // Parses a timestamp, taking any without an offset to be in timezone. None
+    // if policy drops it.
+    pub fn parse(
+        &self,
+        s: &str,
+        timezone: Tz,
+        policy: DstPolicy,
+    ) -> Result<Option<DateTime<Tz>>, Box<dyn Error>> {
+        let nanos_per_unit = match self {
+            Timestamp::IQFeed => {
+                let naive = crate::iqfeed_date_time::parse_naive(s)?;
+                return localize(&naive, timezone, policy);
+            }
+            Timestamp::Iso8601 => {
+                if let Ok(date_time) = DateTime::parse_from_rfc3339(s) {
+                    return Ok(Some(date_time.with_timezone(&timezone)));
+                }
+                let naive = ISO8601_LOCAL
+                    .iter()
+                    .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+                    .ok_or_else(|| format!("Invalid ISO-8601 timestamp {:?}", s))?;
+                return localize(&naive, timezone, policy);
+            }
+            Timestamp::Format(format) if OFFSETS.iter().any(|o| format.contains(o)) => {
+                let date_time = DateTime::parse_from_str(s, format)?;
+                return Ok(Some(date_time.with_timezone(&timezone)));
+            }
+            Timestamp::Format(format) => {
+                let naive = NaiveDateTime::parse_from_str(s, format)?;
+                return localize(&naive, timezone, policy);
+            }
+            Timestamp::Seconds => 1_000_000_000,
+            Timestamp::Millis => 1_000_000,
+            Timestamp::Micros => 1_000,
+            Timestamp::Nanos => 1,
+        };
+        let (whole, fraction) = s.trim().split_once('.').unwrap_or((s.trim(), ""));
+        let whole = whole.parse::<i64>()?;
+        // fractions of a unit, to the nanosecond
+        let mut nanos = 0;
+        let mut scale = nanos_per_unit;
+        for digit in fraction.chars() {
+            scale /= 10;
+            if scale == 0 {
+                break;
+            }
+            nanos += digit.to_digit(10).ok_or("invalid fractional timestamp")? as i64 * scale;
+        }
+        let seconds = whole.div_euclid(1_000_000_000 / nanos_per_unit);
+        let nanos = whole.rem_euclid(1_000_000_000 / nanos_per_unit) * nanos_per_unit + nanos;
+        let date_time = Utc
+            .timestamp_opt(seconds, nanos as u32)
+            .single()
+            .ok_or("timestamp out of range")?;
+        Ok(Some(date_time.with_timezone(&timezone)))
+    }
+}
+
+// Parses the date_time of bars, events and labels, which feat writes in the
+// same format as IQFeed's timestamps but without an offset, so a time that
+// happens twice when clocks go back is taken to be the first.
+pub fn parse_local(s: &str, timezone: Tz) -> Result<DateTime<Tz>, Box<dyn Error>> {
+    Timestamp::IQFeed
+        .parse(s, timezone, DstPolicy::Earliest)?
+        .ok_or_else(|| format!("Invalid timestamp {:?}", s).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::America::New_York;
+
+    fn utc(s: &str) -> DateTime<Tz> {
+        let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        Utc.from_utc_datetime(&naive).with_timezone(&chrono_tz::UTC)
+    }
+
+    fn parse_new_york(s: &str, policy: DstPolicy) -> Result<Option<DateTime<Tz>>, Box<dyn Error>> {
+        Timestamp::IQFeed.parse(s, New_York, policy)
+    }
+
+    #[test]
+    fn skipped_times_when_clocks_go_forward() {
+        // 02:00 EST jumps to 03:00 EDT, so 02:30 never happens
+        let s = "2021-03-14 02:30:00.000000";
+        assert!(parse_new_york(s, DstPolicy::Error).is_err());
+        // taken as EST, which is 03:30 EDT
+        let shifted = Some(utc("2021-03-14 07:30:00"));
+        assert_eq!(parse_new_york(s, DstPolicy::Earliest).unwrap(), shifted);
+        assert_eq!(parse_new_york(s, DstPolicy::Latest).unwrap(), shifted);
+        assert_eq!(parse_new_york(s, DstPolicy::Drop).unwrap(), None);
+    }
+
+    #[test]
+    fn repeated_times_when_clocks_go_back() {
+        // 02:00 EDT goes back to 01:00 EST, so 01:30 happens twice
+        let s = "2021-11-07 01:30:00.000000";
+        assert!(parse_new_york(s, DstPolicy::Error).is_err());
+        assert_eq!(
+            parse_new_york(s, DstPolicy::Earliest).unwrap(),
+            Some(utc("2021-11-07 05:30:00"))
+        );
+        assert_eq!(
+            parse_new_york(s, DstPolicy::Latest).unwrap(),
+            Some(utc("2021-11-07 06:30:00"))
+        );
+        assert_eq!(parse_new_york(s, DstPolicy::Drop).unwrap(), None);
+    }
+
+    #[test]
+    fn unambiguous_times_ignore_the_policy() {
+        for policy in &[
+            DstPolicy::Error,
+            DstPolicy::Earliest,
+            DstPolicy::Latest,
+            DstPolicy::Drop,
+        ] {
+            assert_eq!(
+                parse_new_york("2021-03-14 03:30:00.000000", *policy).unwrap(),
+                Some(utc("2021-03-14 07:30:00"))
+            );
+            assert_eq!(
+                parse_new_york("2021-11-07 02:30:00.000000", *policy).unwrap(),
+                Some(utc("2021-11-07 07:30:00"))
+            );
+        }
+    }
+
+    #[test]
+    fn timestamp_types() {
+        let parse = |s: &str| s.parse::<Timestamp>().unwrap();
+        assert_eq!(parse("iqfeed"), Timestamp::IQFeed);
+        assert_eq!(parse("string"), Timestamp::IQFeed);
+        assert_eq!(parse("rfc3339"), Timestamp::Iso8601);
+        assert_eq!(parse("unix"), Timestamp::Seconds);
+        assert_eq!(parse("unix_ns"), Timestamp::Nanos);
+        assert_eq!(
+            parse("%Y%m%d %H%M%S"),
+            Timestamp::Format(String::from("%Y%m%d %H%M%S"))
+        );
+        assert!("unix_days".parse::<Timestamp>().is_err());
+    }
+
+    #[test]
+    fn epochs_with_fractions() {
+        let parse = |timestamp: &str, s: &str| {
+            timestamp
+                .parse::<Timestamp>()
+                .unwrap()
+                .parse(s, chrono_tz::UTC, DstPolicy::Error)
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(parse("unix", "1631539800"), utc("2021-09-13 13:30:00"));
+        assert_eq!(
+            parse("unix_s", "1631539800.123456789"),
+            utc("2021-09-13 13:30:00.123456789")
+        );
+        assert_eq!(
+            parse("unix_ms", "1631539800123"),
+            utc("2021-09-13 13:30:00.123")
+        );
+        assert_eq!(
+            parse("unix_ms", "1631539800123.456"),
+            utc("2021-09-13 13:30:00.123456")
+        );
+        assert_eq!(
+            parse("unix_us", "1631539800123456.7"),
+            utc("2021-09-13 13:30:00.1234567")
+        );
+        assert_eq!(
+            parse("unix_ns", "1631539800123456789"),
+            utc("2021-09-13 13:30:00.123456789")
+        );
+        // anything past a nanosecond is dropped
+        assert_eq!(
+            parse("unix_us", "1631539800123456.7891"),
+            utc("2021-09-13 13:30:00.123456789")
+        );
+        assert_eq!(
+            parse("unix_ns", "1631539800123456789.9"),
+            utc("2021-09-13 13:30:00.123456789")
+        );
+    }
+
+    #[test]
+    fn epochs_are_converted_to_the_timezone() {
+        let date_time = Timestamp::Seconds
+            .parse("1631539800", New_York, DstPolicy::Error)
+            .unwrap()
+            .unwrap();
+        assert_eq!(date_time.timezone(), New_York);
+        assert_eq!(date_time.naive_local().to_string(), "2021-09-13 09:30:00");
+    }
+}